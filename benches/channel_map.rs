@@ -0,0 +1,68 @@
+//! VC→スレッドのマップを Mutex<HashMap> と DashMap で比較するベンチマーク
+//!
+//! 多数のVCへ同時に参加があった状況を想定し、並行して登録/参照を行う
+
+use std::{collections::HashMap, sync::Arc};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dashmap::DashMap;
+use tokio::{runtime::Runtime, sync::Mutex};
+
+/// 同時に処理するタスク数
+const TASKS: u64 = 64;
+/// 1タスクあたりの登録/参照回数
+const OPS_PER_TASK: u64 = 100;
+
+/// Mutex<HashMap> に並行して登録/参照する
+async fn mutex_hash_map(map: Arc<Mutex<HashMap<u64, u64>>>) {
+    let handles = (0..TASKS)
+        .map(|task| {
+            let map = map.clone();
+            tokio::spawn(async move {
+                for i in 0..OPS_PER_TASK {
+                    let key = task * OPS_PER_TASK + i;
+                    map.lock().await.insert(key, key);
+                    let _ = map.lock().await.get(&key).copied();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+/// DashMap に並行して登録/参照する
+async fn dash_map(map: Arc<DashMap<u64, u64>>) {
+    let handles = (0..TASKS)
+        .map(|task| {
+            let map = map.clone();
+            tokio::spawn(async move {
+                for i in 0..OPS_PER_TASK {
+                    let key = task * OPS_PER_TASK + i;
+                    map.insert(key, key);
+                    let _ = map.get(&key).map(|v| *v);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+fn bench_channel_map(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("channel_map");
+    group.bench_function(BenchmarkId::new("mutex_hash_map", TASKS), |b| {
+        b.to_async(&rt)
+            .iter(|| mutex_hash_map(Arc::new(Mutex::new(HashMap::new()))))
+    });
+    group.bench_function(BenchmarkId::new("dash_map", TASKS), |b| {
+        b.to_async(&rt).iter(|| dash_map(Arc::new(DashMap::new())))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_channel_map);
+criterion_main!(benches);
@@ -1,41 +1,947 @@
-use anyhow::{Context as _, Result};
-use config::Config;
-use serenity::model::id::ChannelId;
-
-#[derive(Debug, Default, serde::Deserialize, PartialEq, Clone)]
-pub struct DiscordConfig {
-    /// Botが動作するカテゴリID
-    pub vc_category: ChannelId,
-    /// 無視するチャンネルID
-    pub vc_ignored_channels: Vec<ChannelId>,
-    /// スレッドを作成するチャンネルID
-    pub thread_channel: ChannelId,
-}
-
-/// アプリケーションの設定
-#[derive(Debug, Default, serde::Deserialize, PartialEq, Clone)]
-pub struct AppConfig {
-    /// Discordの設定
-    pub discord: DiscordConfig,
-}
-
-impl AppConfig {
-    /// 設定を読み込む
-    pub fn load_config(basedir: &str) -> Result<AppConfig> {
-        // 設定ファイルのパス
-        let path = format!("{}/config.toml", basedir);
-        // 設定ファイルを読み込む
-        let config = Config::builder()
-            // Add in `./Settings.toml`
-            .add_source(config::File::with_name(&path))
-            // Add in settings from the environment (with a prefix of APP)
-            // Eg.. `APP_DEBUG=1 ./target/app` would set the `debug` key
-            .add_source(config::Environment::with_prefix("APP"))
-            .build()?;
-        // 設定ファイルをパース
-        let app_config = config
-            .try_deserialize::<AppConfig>()
-            .context("設定ファイルの読み込みに失敗")?;
-        Ok(app_config)
-    }
-}
+mod snowflake;
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+use chrono_tz::Tz;
+use config::Config;
+use log::warn;
+use serenity::model::{
+    application::component::ButtonStyle,
+    channel::ReactionType,
+    id::{ChannelId, GuildId, RoleId, UserId},
+};
+
+use self::snowflake::{
+    deserialize_channel_id, deserialize_channel_id_map, deserialize_channel_ids,
+    deserialize_optional_channel_id, deserialize_role_ids,
+};
+
+#[derive(Debug, Default, serde::Deserialize, PartialEq, Clone)]
+pub struct DiscordConfig {
+    /// Botのトークン (未指定の場合は環境変数 `DISCORD_TOKEN` を使う)
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Botのトークンが書かれたファイルのパス (Docker secrets など。`token` より優先される)
+    #[serde(default)]
+    pub token_file: Option<PathBuf>,
+    /// サーバーごとに別のBotのトークンを使う場合の、サーバーID→トークンのマップ
+    #[serde(default)]
+    pub guild_tokens: HashMap<GuildId, String>,
+    /// サーバーごとに別のカテゴリ/チャンネルを使う場合の、サーバーID→カテゴリとチャンネルのマップ
+    /// `guild_tokens` に指定したサーバーは、既定のカテゴリ/チャンネルがあるサーバーとは別なので指定が必要
+    #[serde(default)]
+    pub guild_channels: HashMap<GuildId, GuildChannels>,
+    /// スラッシュコマンドを登録する範囲
+    #[serde(default)]
+    pub command_scope: CommandScope,
+    /// 開発用のサーバーID (指定した場合は command_scope に関わらず、このサーバーにコマンドを登録する)
+    #[serde(default)]
+    pub dev_guild_id: Option<GuildId>,
+    /// Botが動作するカテゴリID
+    #[serde(deserialize_with = "deserialize_channel_id")]
+    pub vc_category: ChannelId,
+    /// 無視するチャンネルID
+    #[serde(deserialize_with = "deserialize_channel_ids")]
+    pub vc_ignored_channels: Vec<ChannelId>,
+    /// 無視するチャンネルIDを1行に1つずつ書いたファイルのパス (`#` 以降はコメント。`vc_ignored_channels` と合わせて使い、変更されたら読み込み直す)
+    #[serde(default)]
+    pub vc_ignored_channels_file: Option<PathBuf>,
+    /// 名前にこの文字列を含むVCは無視する (`regex-name-patterns` featureが有効な場合は正規表現)
+    #[serde(default)]
+    pub vc_ignored_name_patterns: Vec<String>,
+    /// スレッドを作成するチャンネルID
+    #[serde(deserialize_with = "deserialize_channel_id")]
+    pub thread_channel: ChannelId,
+    /// 特定のVCだけ別のチャンネルにスレッドを作成する場合の、VC→チャンネルのマップ (`thread_channel` より優先される)
+    #[serde(default, deserialize_with = "deserialize_channel_id_map")]
+    pub vc_thread_channel_overrides: HashMap<ChannelId, ChannelId>,
+    /// VCに参加しても無視するユーザーID
+    #[serde(default)]
+    pub vc_ignored_user_ids: Vec<UserId>,
+    /// VCのオーナー以外にVCの名前の変更を許可するロールID
+    #[serde(default, deserialize_with = "deserialize_role_ids")]
+    pub rename_allowed_roles: Vec<RoleId>,
+    /// Botユーザーの参加を無視するか
+    #[serde(default = "default_true")]
+    pub ignore_bots: bool,
+    /// カテゴリ内のステージチャンネルもVCとして管理するか
+    #[serde(default)]
+    pub manage_stage_channels: bool,
+    /// スレッドを作成するのに必要なVCの人数
+    #[serde(default = "default_min_members_to_create_thread")]
+    pub min_members_to_create_thread: usize,
+    /// 同じVCで再びスレッドを作成できるようになるまでの秒数 (0で無効)
+    #[serde(default)]
+    pub thread_creation_cooldown_secs: u64,
+    /// スレッド名の前にVCの参加人数を表示するか
+    #[serde(default)]
+    pub show_member_count_in_thread_name: bool,
+    /// スレッド名の前に表示する参加人数の書式 (`{count}` が人数に置き換えられる)
+    #[serde(default = "default_thread_name_member_count_format")]
+    pub thread_name_member_count_format: String,
+    /// スレッドの作成時に、VCの参加者をスレッドのメンバーに追加するか
+    #[serde(default)]
+    pub auto_add_thread_members: bool,
+    /// スレッドの低速モードの秒数 (0で無効)
+    #[serde(default)]
+    pub thread_slowmode_seconds: u16,
+    /// スレッドの作成からこの秒数が経ったら低速モードを解除する (未指定の場合は解除しない)
+    #[serde(default)]
+    pub slowmode_lift_after_seconds: Option<u64>,
+    /// VC作成時に議題メッセージに付けるリアクションの絵文字 (未指定の場合は付けない)
+    #[serde(default)]
+    pub vc_creation_reaction: Option<String>,
+    /// スレッドで発言がない状態がこの分数続いたら声をかける (未指定の場合は声をかけない)
+    #[serde(default)]
+    pub inactivity_warning_minutes: Option<u64>,
+    /// 同時に管理するVCの上限
+    #[serde(default = "default_max_concurrent_sessions")]
+    pub max_concurrent_sessions: usize,
+    /// 同時に作成するスレッドの上限 (多くのVCが一斉に始まった時にレートリミットにかからないよう、超えた分は順番待ちにする)
+    #[serde(default = "default_max_concurrent_thread_creations")]
+    pub max_concurrent_thread_creations: usize,
+    /// VCとスレッドの対応などを記録するマップの上限 (未指定の場合は無制限、超えた場合は古いものから削除する)
+    #[serde(default)]
+    pub max_map_size: Option<usize>,
+    /// スレッドに投稿する参加/退出メッセージの書式
+    #[serde(default)]
+    pub message_format: MessageFormat,
+    /// ボタン/ダイアログのエラーメッセージを本人にのみ表示するか
+    #[serde(default = "default_true")]
+    pub ephemeral_errors: bool,
+    /// 名前変更の成功メッセージを本人にのみ表示するか
+    #[serde(default)]
+    pub ephemeral_success: bool,
+    /// 名前変更ダイアログで名前を変更できた時の返答の仕方
+    #[serde(default)]
+    pub rename_success_response: RenameSuccessResponse,
+    /// VCの名前に使える文字
+    #[serde(default)]
+    pub vc_name_char_filter: VcNameCharFilter,
+    /// 会話がなかったVCの終了時にスレッドをどうするか
+    #[serde(default)]
+    pub on_empty_session: EmptySessionAction,
+    /// 会話がなかったVCの終了時に、スレッドを後始末する前に投稿するメッセージ (未指定の場合は投稿しない)
+    #[serde(default)]
+    pub empty_session_message: Option<String>,
+    /// 会話があったVCの終了時にスレッドをどうするか
+    #[serde(default)]
+    pub on_active_session: ActiveSessionAction,
+    /// 非推奨: `on_active_session = "lock"` を使う (読み込み時に `on_active_session` に置き換える)
+    #[serde(default)]
+    pub lock_on_end: Option<bool>,
+    /// VC終了後、スレッドを後始末するまでの待ち時間(秒)
+    #[serde(default = "default_post_vc_grace_period_secs")]
+    pub post_vc_grace_period_secs: u64,
+    /// Botの操作を記録する監査ログチャンネル
+    #[serde(default, deserialize_with = "deserialize_optional_channel_id")]
+    pub audit_log_channel: Option<ChannelId>,
+    /// VCのスレッドの発言を転送して、VCに参加できない人も流れを追えるようにするチャンネル (未指定の場合は転送しない)
+    #[serde(default, deserialize_with = "deserialize_optional_channel_id")]
+    pub live_transcript_channel: Option<ChannelId>,
+    /// スレッドの参加メッセージに付けるボタン
+    #[serde(default = "default_buttons")]
+    pub buttons: Vec<ButtonConfig>,
+    /// VCで始まったイベントに合わせて、イベント名のスレッドを作成/後始末するか
+    #[serde(default)]
+    pub integrate_with_events: bool,
+    /// VCの参加人数が人数上限のこの割合に達したらスレッドで警告する (0の場合は警告しない)
+    #[serde(default)]
+    pub user_limit_warning_threshold: f32,
+    /// ボタン(名前変更ボタンなど)を付けるメッセージ
+    #[serde(default)]
+    pub rename_button_placement: ButtonPlacement,
+    /// 名前変更ダイアログの入力欄の見出し
+    #[serde(default = "default_rename_modal_label")]
+    pub rename_modal_label: String,
+    /// 名前変更ダイアログの入力欄の例文
+    #[serde(default = "default_rename_modal_placeholder")]
+    pub rename_modal_placeholder: String,
+    /// 名前変更ダイアログに、話題を入力する欄を追加するか
+    #[serde(default)]
+    pub show_topic_input: bool,
+    /// 参加メッセージに、参加したユーザーのミュート状態を表示するか
+    #[serde(default)]
+    pub show_mute_status: bool,
+    /// VCのステータス(トピック)が変更されたら、スレッドに知らせるか
+    #[serde(default)]
+    pub sync_vc_status_to_thread: bool,
+    /// VCが作成されたら、誰かが参加する前にスレッドを作成するチャンネルで知らせるか
+    #[serde(default)]
+    pub announce_new_vc: bool,
+    /// VCの人数制限が変更されたら、スレッドに知らせるか
+    #[serde(default = "default_true")]
+    pub notify_user_limit_changes: bool,
+    /// 1回のセッションで名前を変更できる回数 (未指定の場合は無制限)
+    #[serde(default)]
+    pub max_renames_per_session: Option<usize>,
+    /// VCの終了時に会話があったか判断するために取得するスレッドのメッセージの件数 (1〜100)
+    /// 新しい順にこの件数だけ取得し、それより古いメッセージは判断に使わない
+    #[serde(default = "default_finalize_scan_limit")]
+    pub finalize_scan_limit: u8,
+    /// VCごとに記録する名前変更の履歴の件数
+    #[serde(default = "default_rename_history_limit")]
+    pub rename_history_limit: usize,
+    /// 時刻の表示に使うタイムゾーン (例: `Asia/Tokyo`。未指定の場合はUTCで表示する)
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// サーバーごとに別のタイムゾーンを使う場合の、サーバーID→タイムゾーンのマップ (`timezone` より優先される)
+    #[serde(default)]
+    pub guild_timezones: HashMap<GuildId, String>,
+}
+
+/// サーバーごとのカテゴリとチャンネル
+#[derive(Debug, Clone, serde::Deserialize, PartialEq, Eq)]
+pub struct GuildChannels {
+    /// Botが動作するカテゴリID
+    #[serde(deserialize_with = "deserialize_channel_id")]
+    pub vc_category: ChannelId,
+    /// スレッドを作成するチャンネルID
+    #[serde(deserialize_with = "deserialize_channel_id")]
+    pub thread_channel: ChannelId,
+}
+
+impl DiscordConfig {
+    /// サーバーのBotが動作するカテゴリを取得する (サーバーごとの指定がなければ `vc_category`)
+    pub fn vc_category_for(&self, guild_id: GuildId) -> ChannelId {
+        self.guild_channels
+            .get(&guild_id)
+            .map_or(self.vc_category, |channels| channels.vc_category)
+    }
+
+    /// サーバーのスレッドを作成するチャンネルを取得する (サーバーごとの指定がなければ `thread_channel`)
+    pub fn thread_channel_for(&self, guild_id: GuildId) -> ChannelId {
+        self.guild_channels
+            .get(&guild_id)
+            .map_or(self.thread_channel, |channels| channels.thread_channel)
+    }
+
+    /// 非推奨の設定を新しい設定に置き換える (新しい設定と食い違う場合はエラーにする)
+    pub fn migrate_deprecated(&mut self) -> Result<()> {
+        if let Some(lock_on_end) = self.lock_on_end.take() {
+            warn!(
+                "lock_on_end は非推奨です。代わりに on_active_session = \"lock\" を使ってください"
+            );
+            match (lock_on_end, self.on_active_session) {
+                (true, ActiveSessionAction::Archive) => {
+                    self.on_active_session = ActiveSessionAction::Lock;
+                }
+                (true, ActiveSessionAction::Nothing) | (false, ActiveSessionAction::Lock) => {
+                    anyhow::bail!(
+                        "lock_on_end と on_active_session が食い違っています。lock_on_end を削除し、on_active_session だけを指定してください"
+                    );
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// サーバーの時刻の表示に使うタイムゾーンを取得する (指定がなければNone)
+    /// 起動時にチェック済みのため、解釈できない場合も指定がないものとして扱う
+    pub fn timezone_for(&self, guild_id: GuildId) -> Option<Tz> {
+        self.guild_timezones
+            .get(&guild_id)
+            .or(self.timezone.as_ref())
+            .and_then(|timezone| timezone.parse().ok())
+    }
+}
+
+/// スレッドの参加メッセージに付けるボタンの設定
+#[derive(Debug, serde::Deserialize, PartialEq, Eq, Clone)]
+pub struct ButtonConfig {
+    /// ボタンのID (押された時の処理はこのIDで決まる)
+    pub custom_id: String,
+    /// ボタンに表示する文字列
+    pub label: String,
+    /// ボタンの色
+    #[serde(default)]
+    pub style: ButtonColour,
+    /// ボタンを押すのに必要な権限
+    #[serde(default)]
+    pub required_permission: ButtonPermission,
+}
+
+/// ボタンの色
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonColour {
+    /// 青
+    Primary,
+    /// 灰色
+    Secondary,
+    /// 緑
+    #[default]
+    Success,
+    /// 赤
+    Danger,
+}
+
+impl From<ButtonColour> for ButtonStyle {
+    fn from(colour: ButtonColour) -> Self {
+        match colour {
+            ButtonColour::Primary => ButtonStyle::Primary,
+            ButtonColour::Secondary => ButtonStyle::Secondary,
+            ButtonColour::Success => ButtonStyle::Success,
+            ButtonColour::Danger => ButtonStyle::Danger,
+        }
+    }
+}
+
+/// ボタンを付けるメッセージ
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonPlacement {
+    /// スレッドの参加メッセージ
+    #[default]
+    ThreadWelcome,
+    /// スレッドを作成するチャンネルの議題メッセージ
+    AgendaMessage,
+    /// 両方
+    Both,
+}
+
+impl ButtonPlacement {
+    /// スレッドの参加メッセージにボタンを付けるか
+    pub fn in_thread_welcome(self) -> bool {
+        matches!(self, ButtonPlacement::ThreadWelcome | ButtonPlacement::Both)
+    }
+
+    /// 議題メッセージにボタンを付けるか
+    pub fn in_agenda_message(self) -> bool {
+        matches!(self, ButtonPlacement::AgendaMessage | ButtonPlacement::Both)
+    }
+}
+
+/// 名前変更ダイアログで名前を変更できた時の返答の仕方
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RenameSuccessResponse {
+    /// スレッドに新しくメッセージを投稿する (`ephemeral_success` が有効な場合は本人にのみ表示する)
+    #[default]
+    NewMessage,
+    /// ボタンを押したメッセージを成功メッセージに書き換える
+    UpdateOriginal,
+    /// 本人にのみ表示する
+    Ephemeral,
+}
+
+/// `unicode_basic` で使える文字の範囲 (結合文字や書字方向の制御文字、絵文字をつなぐ文字は含めない)
+const UNICODE_BASIC_RANGES: [RangeInclusive<char>; 13] = [
+    // Basic Latin (制御文字を除く)
+    ' '..='~',
+    // CJKの記号と句読点 (全角スペース、「」、。など)
+    '\u{3000}'..='\u{303F}',
+    // ひらがな
+    '\u{3040}'..='\u{309F}',
+    // カタカナ
+    '\u{30A0}'..='\u{30FF}',
+    // CJK統合漢字
+    '\u{4E00}'..='\u{9FFF}',
+    // 全角英数字と記号 (！〜～)
+    '\u{FF01}'..='\u{FF5E}',
+    // 絵文字として表示する異体字セレクタ (❤️ など、記号の後に付く)
+    '\u{FE0F}'..='\u{FE0F}',
+    // その他の記号
+    '\u{2600}'..='\u{26FF}',
+    // 装飾記号
+    '\u{2700}'..='\u{27BF}',
+    // その他の記号と絵文字
+    '\u{1F300}'..='\u{1F5FF}',
+    // 顔文字
+    '\u{1F600}'..='\u{1F64F}',
+    // 交通と地図の記号
+    '\u{1F680}'..='\u{1F6FF}',
+    // 補助記号と絵文字
+    '\u{1F900}'..='\u{1F9FF}',
+];
+
+/// VCの名前に使える文字 (Zalgoテキストや書字方向の制御文字などでDiscordの表示が崩れるのを防ぐ)
+#[derive(Debug, Clone, Default, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VcNameCharFilter {
+    /// 制限しない
+    #[default]
+    None,
+    /// ASCII文字のみ
+    AsciiOnly,
+    /// 英数字、ひらがな、カタカナ、漢字と一般的な絵文字のみ
+    UnicodeBasic,
+    /// 指定した範囲の文字のみ (例: `{ custom = [{ start = "a", end = "z" }] }`)
+    Custom(Vec<RangeInclusive<char>>),
+}
+
+impl VcNameCharFilter {
+    /// 名前の全ての文字が使えるか
+    pub fn allows(&self, name: &str) -> bool {
+        let ranges: &[RangeInclusive<char>] = match self {
+            VcNameCharFilter::None => return true,
+            VcNameCharFilter::AsciiOnly => return name.is_ascii(),
+            VcNameCharFilter::UnicodeBasic => &UNICODE_BASIC_RANGES,
+            VcNameCharFilter::Custom(ranges) => ranges,
+        };
+        name.chars()
+            .all(|c| ranges.iter().any(|range| range.contains(&c)))
+    }
+}
+
+/// ボタンを押すのに必要な権限
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonPermission {
+    /// VCのオーナー(または名前の変更を許可されたロール)のみ
+    #[default]
+    VcOwner,
+    /// 誰でも
+    Everyone,
+}
+
+/// スレッドに投稿するメッセージの書式
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageFormat {
+    /// プレーンテキスト
+    #[default]
+    PlainText,
+    /// 埋め込み
+    Embed,
+}
+
+/// スラッシュコマンドを登録する範囲
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandScope {
+    /// Botが参加しているサーバーごとに登録する (すぐに反映される)
+    #[default]
+    Guild,
+    /// グローバルに登録する (反映されるまで最大1時間かかる)
+    Global,
+}
+
+/// 会話がなかったVCの終了時のスレッドの扱い
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptySessionAction {
+    /// スレッドを削除する
+    #[default]
+    Delete,
+    /// スレッドをアーカイブする
+    Archive,
+    /// 何もしない
+    Nothing,
+}
+
+/// 会話があったVCの終了時のスレッドの扱い
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActiveSessionAction {
+    /// スレッドをアーカイブする
+    #[default]
+    Archive,
+    /// スレッドをアーカイブしてロックする
+    Lock,
+    /// 何もしない
+    Nothing,
+}
+
+/// Discordの低速モードの上限(秒)
+const MAX_SLOWMODE_SECONDS: u16 = 21600;
+
+/// Discordで一度に取得できるメッセージの上限
+const MAX_MESSAGES_PER_REQUEST: u8 = 100;
+
+/// 名前変更ボタンのID
+pub const RENAME_BUTTON_ID: &str = "rename_button";
+
+/// 処理が実装されているボタンのID
+const SUPPORTED_BUTTON_IDS: &[&str] = &[RENAME_BUTTON_ID];
+
+/// 1行に並べられるボタンの上限
+const MAX_BUTTONS_PER_ROW: usize = 5;
+
+/// serdeのデフォルト値(true)
+fn default_true() -> bool {
+    true
+}
+
+/// VC終了後、スレッドを後始末するまでの待ち時間(秒)のデフォルト値
+fn default_post_vc_grace_period_secs() -> u64 {
+    5
+}
+
+/// スレッド名の前に表示する参加人数の書式のデフォルト値
+fn default_thread_name_member_count_format() -> String {
+    "「{count}人」".to_string()
+}
+
+/// 参加メッセージに付けるボタンのデフォルト値 (名前変更ボタンのみ)
+fn default_buttons() -> Vec<ButtonConfig> {
+    vec![ButtonConfig {
+        custom_id: RENAME_BUTTON_ID.to_string(),
+        label: "📝チャンネル名を変える".to_string(),
+        style: ButtonColour::Success,
+        required_permission: ButtonPermission::VcOwner,
+    }]
+}
+
+/// 名前変更ダイアログの入力欄の見出しのデフォルト値
+/// 英語の場合は "What's this VC about?" など
+fn default_rename_modal_label() -> String {
+    "VCのテーマは？".to_string()
+}
+
+/// 名前変更ダイアログの入力欄の例文のデフォルト値
+/// 英語の場合は "Fortnite, Word chain, Karaoke,..." など
+fn default_rename_modal_placeholder() -> String {
+    "フォートナイト, しりとり, カラオケ,...".to_string()
+}
+
+/// VCごとに記録する名前変更の履歴の件数のデフォルト値
+fn default_rename_history_limit() -> usize {
+    10
+}
+
+fn default_finalize_scan_limit() -> u8 {
+    50
+}
+
+/// 同時に管理するVCの上限のデフォルト値
+fn default_max_concurrent_sessions() -> usize {
+    50
+}
+
+fn default_max_concurrent_thread_creations() -> usize {
+    3
+}
+
+/// スレッドを作成するのに必要なVCの人数のデフォルト値
+fn default_min_members_to_create_thread() -> usize {
+    1
+}
+
+/// アプリケーションの設定
+#[derive(Debug, Default, serde::Deserialize, PartialEq, Clone)]
+pub struct AppConfig {
+    /// ヘルスチェック用のHTTPサーバーのポート (未指定の場合は起動しない)
+    #[serde(default)]
+    pub health_port: Option<u16>,
+    /// Prometheusから収集するためのメトリクスのHTTPサーバーのポート (未指定の場合は起動しない)
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
+    /// VCのセッションの開始/終了を通知するWebhookのURL (未指定の場合は通知しない)
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    /// セッションの開始時にWebhookで通知するか
+    #[serde(default = "default_true")]
+    pub webhook_on_start: bool,
+    /// セッションの終了時にWebhookで通知するか
+    #[serde(default = "default_true")]
+    pub webhook_on_end: bool,
+    /// 終了したセッションを追記するCSVファイルのパス (未指定の場合は記録しない)
+    #[serde(default)]
+    pub telemetry_file: Option<PathBuf>,
+    /// Discordの設定
+    pub discord: DiscordConfig,
+}
+
+impl AppConfig {
+    /// 設定を読み込む
+    pub fn load_config(basedir: &str) -> Result<AppConfig> {
+        // 設定ファイルのパス
+        let path = Self::config_path(basedir);
+        // 設定ファイルを読み込む
+        let config = Config::builder()
+            // Add in `./Settings.toml`
+            .add_source(config::File::with_name(&path))
+            // Add in settings from the environment (with a prefix of APP)
+            // Eg.. `APP_DEBUG=1 ./target/app` would set the `debug` key
+            .add_source(config::Environment::with_prefix("APP"))
+            .build()?;
+        // 設定ファイルをパース
+        let mut app_config = config
+            .try_deserialize::<AppConfig>()
+            .context("設定ファイルの読み込みに失敗")?;
+        // 非推奨の設定を新しい設定に置き換える
+        app_config.discord.migrate_deprecated()?;
+        // 設定値をチェック
+        app_config.validate().context("設定値が不正です")?;
+        Ok(app_config)
+    }
+
+    /// 設定値をチェックする
+    fn validate(&self) -> Result<()> {
+        // 低速モードはDiscordの上限(6時間)まで
+        if self.discord.thread_slowmode_seconds > MAX_SLOWMODE_SECONDS {
+            anyhow::bail!(
+                "thread_slowmode_seconds は {} 秒以下にしてください",
+                MAX_SLOWMODE_SECONDS
+            );
+        }
+        // リアクションは絵文字として解釈できるもののみ
+        if let Some(emoji) = &self.discord.vc_creation_reaction {
+            if ReactionType::try_from(emoji.as_str()).is_err() {
+                anyhow::bail!("vc_creation_reaction が絵文字ではありません: {}", emoji);
+            }
+        }
+        // 専用のトークンを使うサーバーは既定のカテゴリ/チャンネルがあるサーバーとは別なので、個別の指定が必要
+        if let Some(guild_id) = self
+            .discord
+            .guild_tokens
+            .keys()
+            .find(|guild_id| !self.discord.guild_channels.contains_key(guild_id))
+        {
+            anyhow::bail!(
+                "guild_tokens に指定したサーバー({}) の vc_category と thread_channel を guild_channels で指定してください",
+                guild_id
+            );
+        }
+        // 範囲を指定しない場合や空の範囲を指定した場合は、どの名前にも変更できなくなる
+        if let VcNameCharFilter::Custom(ranges) = &self.discord.vc_name_char_filter {
+            if ranges.is_empty() || ranges.iter().any(|range| range.is_empty()) {
+                anyhow::bail!(
+                    "vc_name_char_filter の custom には start が end 以下の範囲を1つ以上指定してください"
+                );
+            }
+        }
+        // スレッドを1つも作成できないと全てのVCが順番待ちのままになる
+        if self.discord.max_concurrent_thread_creations == 0 {
+            anyhow::bail!("max_concurrent_thread_creations は 1 以上にしてください");
+        }
+        // 一度に取得できるメッセージは100件まで
+        if !(1..=MAX_MESSAGES_PER_REQUEST).contains(&self.discord.finalize_scan_limit) {
+            anyhow::bail!(
+                "finalize_scan_limit は 1 以上 {} 以下にしてください",
+                MAX_MESSAGES_PER_REQUEST
+            );
+        }
+        // 人数上限の割合は0〜1の範囲
+        if !(0.0..=1.0).contains(&self.discord.user_limit_warning_threshold) {
+            anyhow::bail!("user_limit_warning_threshold は 0 以上 1 以下にしてください");
+        }
+        // タイムゾーンは解釈できるもののみ
+        for timezone in self
+            .discord
+            .timezone
+            .iter()
+            .chain(self.discord.guild_timezones.values())
+        {
+            if let Err(why) = timezone.parse::<Tz>() {
+                anyhow::bail!("timezone が不正です: {} ({})", timezone, why);
+            }
+        }
+        // ボタンは1行に並べられる数まで、IDは重複なく処理が実装されているもののみ
+        let buttons = &self.discord.buttons;
+        if buttons.len() > MAX_BUTTONS_PER_ROW {
+            anyhow::bail!("buttons は {} 個以下にしてください", MAX_BUTTONS_PER_ROW);
+        }
+        let mut custom_ids = HashSet::new();
+        for button in buttons {
+            if !SUPPORTED_BUTTON_IDS.contains(&button.custom_id.as_str()) {
+                anyhow::bail!(
+                    "buttons の custom_id が不明です: {} (使えるID: {})",
+                    button.custom_id,
+                    SUPPORTED_BUTTON_IDS.join(", ")
+                );
+            }
+            if !custom_ids.insert(button.custom_id.as_str()) {
+                anyhow::bail!(
+                    "buttons の custom_id が重複しています: {}",
+                    button.custom_id
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Botのトークンを取得する
+    pub fn token(&self) -> Result<String> {
+        let token = read_secret(&self.discord.token, &self.discord.token_file)?;
+        match token {
+            Some(token) => Ok(token),
+            None => std::env::var("DISCORD_TOKEN").context("トークンが指定されていません"),
+        }
+    }
+
+    /// 設定ファイルのパスを取得する
+    /// `yaml-config` featureが有効な場合は `config.yaml` があればそちらを優先し、なければ `config.toml` を使う
+    #[cfg(feature = "yaml-config")]
+    fn config_path(basedir: &str) -> String {
+        let yaml_path = format!("{}/config.yaml", basedir);
+        if std::path::Path::new(&yaml_path).exists() {
+            return yaml_path;
+        }
+        format!("{}/config.toml", basedir)
+    }
+
+    /// 設定ファイルのパスを取得する
+    #[cfg(not(feature = "yaml-config"))]
+    fn config_path(basedir: &str) -> String {
+        format!("{}/config.toml", basedir)
+    }
+}
+
+/// テスト用に設定を組み立てる
+/// 設定ファイルを用意しなくても、必要な項目だけを指定して設定を作れるようにする
+#[cfg(test)]
+pub struct AppConfigBuilder {
+    app_config: AppConfig,
+}
+
+#[cfg(test)]
+impl Default for AppConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+impl AppConfigBuilder {
+    /// 設定ファイルで省略した場合と同じ既定値で組み立てを始める
+    pub fn new() -> Self {
+        let app_config = serde_json::from_value(serde_json::json!({
+            "discord": {
+                "vc_category": "0",
+                "vc_ignored_channels": [],
+                "thread_channel": "0",
+            },
+        }))
+        .expect("既定の設定の作成に失敗");
+        Self { app_config }
+    }
+
+    /// Botが動作するカテゴリIDを指定する
+    pub fn with_vc_category(mut self, vc_category: ChannelId) -> Self {
+        self.app_config.discord.vc_category = vc_category;
+        self
+    }
+
+    /// スレッドを作成するチャンネルIDを指定する
+    pub fn with_thread_channel(mut self, thread_channel: ChannelId) -> Self {
+        self.app_config.discord.thread_channel = thread_channel;
+        self
+    }
+
+    /// 無視するチャンネルIDを指定する
+    pub fn with_ignored_channels(mut self, vc_ignored_channels: Vec<ChannelId>) -> Self {
+        self.app_config.discord.vc_ignored_channels = vc_ignored_channels;
+        self
+    }
+
+    /// Discordの設定を直接変更する
+    pub fn with_discord(mut self, f: impl FnOnce(&mut DiscordConfig)) -> Self {
+        f(&mut self.app_config.discord);
+        self
+    }
+
+    /// 設定値をチェックして設定を作成する
+    pub fn build(mut self) -> Result<AppConfig> {
+        self.app_config.discord.migrate_deprecated()?;
+        self.app_config.validate().context("設定値が不正です")?;
+        Ok(self.app_config)
+    }
+}
+
+/// 秘密情報を取得する
+/// ファイルのパスが指定されている場合はファイルから読み込み(末尾の空白は除く)、そうでなければ値をそのまま使う
+fn read_secret(value: &Option<String>, file: &Option<PathBuf>) -> Result<Option<String>> {
+    match file {
+        Some(path) => {
+            let secret = std::fs::read_to_string(path).with_context(|| {
+                format!("秘密情報のファイルの読み込みに失敗: {}", path.display())
+            })?;
+            Ok(Some(secret.trim_end().to_string()))
+        }
+        None => Ok(value.clone()),
+    }
+}
+
+/// チャンネルIDを1行に1つずつ書いたファイルを読み込む
+pub fn read_channel_id_list(path: &Path) -> Result<Vec<ChannelId>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("チャンネルIDのファイルの読み込みに失敗: {}", path.display()))?;
+    parse_channel_id_list(&content)
+        .with_context(|| format!("チャンネルIDのファイルが不正です: {}", path.display()))
+}
+
+/// 1行に1つずつ書かれたチャンネルIDを読み込む (`#` 以降はコメント、空行は無視する)
+fn parse_channel_id_list(content: &str) -> Result<Vec<ChannelId>> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let id = line.split('#').next().unwrap_or_default().trim();
+            (!id.is_empty()).then_some((i + 1, id))
+        })
+        .map(|(line_number, id)| {
+            id.parse()
+                .map(ChannelId)
+                .with_context(|| format!("{}行目がチャンネルIDではありません: {}", line_number, id))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_id_list_skips_comments_and_blank_lines() {
+        let content = "# 作成用のVC\n1\n\n2 # 雑談\n";
+        assert_eq!(
+            parse_channel_id_list(content).unwrap(),
+            vec![ChannelId(1), ChannelId(2)]
+        );
+        assert!(parse_channel_id_list("general").is_err());
+    }
+
+    #[test]
+    fn builder_uses_config_file_defaults() {
+        let app_config = AppConfigBuilder::new()
+            .with_vc_category(ChannelId(1))
+            .with_thread_channel(ChannelId(2))
+            .with_ignored_channels(vec![ChannelId(3)])
+            .build()
+            .unwrap();
+        assert_eq!(app_config.discord.vc_category, ChannelId(1));
+        assert_eq!(app_config.discord.thread_channel, ChannelId(2));
+        assert_eq!(app_config.discord.vc_ignored_channels, vec![ChannelId(3)]);
+        assert!(app_config.discord.ignore_bots);
+        assert_eq!(app_config.discord.min_members_to_create_thread, 1);
+        assert_eq!(app_config.discord.max_concurrent_sessions, 50);
+    }
+
+    #[test]
+    fn builder_rejects_invalid_config() {
+        let result = AppConfigBuilder::new()
+            .with_discord(|discord| discord.thread_slowmode_seconds = MAX_SLOWMODE_SECONDS + 1)
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn unicode_basic_filter_rejects_combining_and_control_chars() {
+        let filter = VcNameCharFilter::UnicodeBasic;
+        assert!(filter.allows("雑談VC「その1」🎮"));
+        assert!(filter.allows("もくもく会"));
+        // 異体字セレクタ付きの絵文字と全角英数字
+        assert!(filter.allows("❤️ＶＣ１！"));
+        // Zalgoテキスト (結合文字)
+        assert!(!filter.allows("Z\u{0336}\u{0334}algo"));
+        // 右から左への書字方向の上書き
+        assert!(!filter.allows("\u{202E}CV"));
+    }
+
+    #[test]
+    fn custom_filter_uses_given_ranges() {
+        let filter = VcNameCharFilter::Custom(vec!['a'..='z', '0'..='9']);
+        assert!(filter.allows("vc1"));
+        assert!(!filter.allows("VC1"));
+        assert!(VcNameCharFilter::None.allows("\u{202E}"));
+        assert!(!VcNameCharFilter::AsciiOnly.allows("雑談"));
+    }
+
+    #[test]
+    fn builder_rejects_empty_custom_filter() {
+        let result = AppConfigBuilder::new()
+            .with_discord(|discord| discord.vc_name_char_filter = VcNameCharFilter::Custom(vec![]))
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn guild_timezone_takes_priority() {
+        let app_config = AppConfigBuilder::new()
+            .with_discord(|discord| {
+                discord.timezone = Some("Asia/Tokyo".to_string());
+                discord
+                    .guild_timezones
+                    .insert(GuildId(1), "America/Sao_Paulo".to_string());
+            })
+            .build()
+            .unwrap();
+        let discord = &app_config.discord;
+        assert_eq!(
+            discord.timezone_for(GuildId(1)),
+            Some(Tz::America__Sao_Paulo)
+        );
+        assert_eq!(discord.timezone_for(GuildId(2)), Some(Tz::Asia__Tokyo));
+    }
+
+    #[test]
+    fn builder_rejects_invalid_guild_timezone() {
+        let result = AppConfigBuilder::new()
+            .with_discord(|discord| {
+                discord
+                    .guild_timezones
+                    .insert(GuildId(1), "Asia/Nowhere".to_string());
+            })
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn deprecated_lock_on_end_maps_to_on_active_session() {
+        let app_config = AppConfigBuilder::new()
+            .with_discord(|discord| discord.lock_on_end = Some(true))
+            .build()
+            .unwrap();
+        assert_eq!(
+            app_config.discord.on_active_session,
+            ActiveSessionAction::Lock
+        );
+
+        let result = AppConfigBuilder::new()
+            .with_discord(|discord| {
+                discord.lock_on_end = Some(true);
+                discord.on_active_session = ActiveSessionAction::Nothing;
+            })
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn guild_tokens_require_guild_channels() {
+        let result = AppConfigBuilder::new()
+            .with_discord(|discord| {
+                discord.guild_tokens.insert(GuildId(1), "token".to_string());
+            })
+            .build();
+        assert!(result.is_err());
+
+        let app_config = AppConfigBuilder::new()
+            .with_thread_channel(ChannelId(2))
+            .with_discord(|discord| {
+                discord.guild_tokens.insert(GuildId(1), "token".to_string());
+                discord.guild_channels.insert(
+                    GuildId(1),
+                    GuildChannels {
+                        vc_category: ChannelId(10),
+                        thread_channel: ChannelId(20),
+                    },
+                );
+            })
+            .build()
+            .unwrap();
+        assert_eq!(
+            app_config.discord.thread_channel_for(GuildId(1)),
+            ChannelId(20)
+        );
+        assert_eq!(
+            app_config.discord.thread_channel_for(GuildId(2)),
+            ChannelId(2)
+        );
+    }
+
+    #[test]
+    fn builder_rejects_duplicate_buttons() {
+        let result = AppConfigBuilder::new()
+            .with_discord(|discord| discord.buttons.extend(default_buttons()))
+            .build();
+        assert!(result.is_err());
+    }
+}
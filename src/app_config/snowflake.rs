@@ -0,0 +1,124 @@
+use std::{collections::HashMap, fmt};
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serenity::model::id::{ChannelId, RoleId};
+
+/// 数値と数値の文字列のどちらでも書けるID
+/// DiscordのAPIと同じように、JSONなどで文字列として書かれたIDも読み込めるようにする
+#[derive(PartialEq, Eq, Hash)]
+struct Snowflake(u64);
+
+impl<'de> Deserialize<'de> for Snowflake {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(SnowflakeVisitor)
+    }
+}
+
+struct SnowflakeVisitor;
+
+impl<'de> Visitor<'de> for SnowflakeVisitor {
+    type Value = Snowflake;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("数値または数値の文字列のID")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        Ok(Snowflake(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        u64::try_from(value)
+            .map(Snowflake)
+            .map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        value
+            .parse()
+            .map(Snowflake)
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+}
+
+/// チャンネルIDを読み込む
+pub fn deserialize_channel_id<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<ChannelId, D::Error> {
+    Snowflake::deserialize(deserializer).map(|id| ChannelId(id.0))
+}
+
+/// 省略できるチャンネルIDを読み込む
+pub fn deserialize_optional_channel_id<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ChannelId>, D::Error> {
+    Option::<Snowflake>::deserialize(deserializer).map(|id| id.map(|id| ChannelId(id.0)))
+}
+
+/// チャンネルIDのリストを読み込む
+pub fn deserialize_channel_ids<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ChannelId>, D::Error> {
+    Vec::<Snowflake>::deserialize(deserializer)
+        .map(|ids| ids.into_iter().map(|id| ChannelId(id.0)).collect())
+}
+
+/// チャンネルID→チャンネルIDのマップを読み込む
+pub fn deserialize_channel_id_map<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<ChannelId, ChannelId>, D::Error> {
+    HashMap::<Snowflake, Snowflake>::deserialize(deserializer).map(|ids| {
+        ids.into_iter()
+            .map(|(key, value)| (ChannelId(key.0), ChannelId(value.0)))
+            .collect()
+    })
+}
+
+/// ロールIDのリストを読み込む
+pub fn deserialize_role_ids<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<RoleId>, D::Error> {
+    Vec::<Snowflake>::deserialize(deserializer)
+        .map(|ids| ids.into_iter().map(|id| RoleId(id.0)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(serde::Deserialize)]
+    struct Channel {
+        #[serde(deserialize_with = "deserialize_channel_id")]
+        id: ChannelId,
+    }
+
+    #[test]
+    fn number_and_string_parse_to_same_channel_id() {
+        let number: Channel = serde_json::from_str(r#"{ "id": 42 }"#).unwrap();
+        let string: Channel = serde_json::from_str(r#"{ "id": "42" }"#).unwrap();
+        assert_eq!(number.id, ChannelId(42));
+        assert_eq!(string.id, ChannelId(42));
+    }
+
+    #[test]
+    fn channel_id_map_accepts_string_keys() {
+        #[derive(serde::Deserialize)]
+        struct Overrides {
+            #[serde(deserialize_with = "deserialize_channel_id_map")]
+            map: HashMap<ChannelId, ChannelId>,
+        }
+
+        let overrides: Overrides =
+            serde_json::from_str(r#"{ "map": { "1": 2, "3": "4" } }"#).unwrap();
+        assert_eq!(
+            overrides.map,
+            HashMap::from([(ChannelId(1), ChannelId(2)), (ChannelId(3), ChannelId(4))])
+        );
+    }
+
+    #[test]
+    fn rejects_non_numeric_string() {
+        let result = serde_json::from_str::<Channel>(r#"{ "id": "general" }"#);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,30 @@
+use serenity::model::channel::ChannelType;
+
+/// 通話ができるチャンネルの種類か判定する
+/// ステージチャンネルは `include_stage` が有効な場合のみ含める
+/// Discordに新しい種類の通話チャンネルが追加された場合は、ここに追加する
+pub(crate) fn is_voice_like(kind: ChannelType, include_stage: bool) -> bool {
+    match kind {
+        ChannelType::Voice => true,
+        ChannelType::Stage => include_stage,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voice_channel_is_always_voice_like() {
+        assert!(is_voice_like(ChannelType::Voice, false));
+        assert!(is_voice_like(ChannelType::Voice, true));
+    }
+
+    #[test]
+    fn stage_channel_is_voice_like_only_when_enabled() {
+        assert!(!is_voice_like(ChannelType::Stage, false));
+        assert!(is_voice_like(ChannelType::Stage, true));
+        assert!(!is_voice_like(ChannelType::Text, true));
+    }
+}
@@ -1,60 +1,745 @@
-use std::{collections::HashMap, time::Duration};
+mod audit;
+mod bot_config;
+mod commands;
+mod error;
+mod ignored_channels;
+mod scheduled_events;
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hash::Hash,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context as _, Result};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use hhmmss::Hhmmss;
-use log::{error, warn};
+use indexmap::IndexMap;
+use log::{debug, error, info, warn};
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
+use serde_json::{json, Value};
+use serenity::builder::{CreateComponents, CreateMessage};
 use serenity::model::{
     application::interaction::{Interaction, InteractionResponseType},
     gateway::Ready,
-    guild::Member,
-    id::ChannelId,
+    guild::{Guild, Member, ScheduledEvent, UnavailableGuild},
+    id::{ChannelId, GuildId, MessageId, ScheduledEventId},
     prelude::{
         component::{ActionRowComponent, ButtonStyle, InputTextStyle},
         interaction::{
             message_component::MessageComponentInteraction, modal::ModalSubmitInteraction,
         },
-        Channel, ChannelType, GuildChannel, Message, UserId,
+        Channel, ChannelType, GuildChannel, Message, ReactionType, User, UserId,
     },
     voice::VoiceState,
 };
-use tokio::time::timeout;
+use tokio::sync::{OnceCell, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+use crate::app_config::{
+    ActiveSessionAction, AppConfig, ButtonConfig, ButtonPermission, CommandScope,
+    EmptySessionAction, MessageFormat, RenameSuccessResponse, RENAME_BUTTON_ID,
+};
+use crate::channel_utils::is_voice_like;
+use crate::{telemetry, webhook};
 
-use crate::app_config::AppConfig;
+use self::audit::AuditAction;
+use self::bot_config::BotConfig;
+use self::error::BotError;
 
 use serenity::async_trait;
 use serenity::prelude::*;
+use serenity::utils::Colour;
+
+/// 予約されたスレッドの更新をまとめて行う間隔
+/// Discordのスレッド編集のレートリミットは1チャンネルあたり10秒に2回程度
+const THREAD_UPDATE_INTERVAL: Duration = Duration::from_secs(6);
+
+/// 埋め込みメッセージの色
+const EMBED_COLOUR: Colour = Colour::BLURPLE;
+
+/// セッション中の名前変更の回数が上限に達した時のメッセージ
+const RENAME_LIMIT_MESSAGE: &str = "❌このセッション中の名前変更の上限に達しました";
+
+/// 終了メッセージに付ける、スレッドを再開するボタンのID
+const RESUME_BUTTON_ID: &str = "resume_button";
+
+/// VCのテキストチャットへの案内メッセージの送信を試みる回数
+const VC_TEXT_MAX_ATTEMPTS: u32 = 3;
+
+/// VCのテキストチャットへの案内メッセージの送信を再試行する間隔
+const VC_TEXT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// スレッドの作成を試みる回数
+const THREAD_CREATE_MAX_ATTEMPTS: u32 = 3;
+
+/// スレッドの作成を再試行する間隔
+const THREAD_CREATE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Discordのメッセージのnonceの最大文字数
+const MESSAGE_NONCE_MAX_CHARS: usize = 25;
+
+/// セッションの途中経過をスレッドに投稿するまでの時間
+const MID_SESSION_SUMMARY_DELAY: Duration = Duration::from_secs(30 * 60);
+
+/// スレッドで発言がない状態が続いていないか確認する間隔
+const INACTIVITY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// スレッドを作成中のVCに参加した場合に、作成が終わったか確認し直す間隔
+const THREAD_CREATION_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// スレッドを作成中のVCに参加した場合に、作成が終わったか確認し直す回数
+const THREAD_CREATION_MAX_RETRIES: u32 = 20;
+
+/// URLらしき文字列にマッチする正規表現
+static URL_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)(https?://|www\.|discord\.gg/)").unwrap());
+
+/// VCの名前として使えるかチェックし、使えない場合は理由を返す
+fn validate_vc_name(name: &str) -> Option<&'static str> {
+    if name.contains('`') {
+        return Some("VCの名前にバッククォート(`)は使えません");
+    }
+    if name.contains("@everyone") || name.contains("@here") {
+        return Some("VCの名前に@everyoneや@hereは使えません");
+    }
+    if URL_PATTERN.is_match(name) {
+        return Some("VCの名前にURLは使えません");
+    }
+    None
+}
+
+/// 人間の発言がなく、このメッセージ数以下のスレッドは作成直後のままとみなす (Botが最初に投稿するメッセージの数)
+const EMPTY_THREAD_MESSAGE_THRESHOLD: usize = 2;
+
+/// 同じスレッドの発言をライブ転送する間隔 (この間に投稿された発言はまとめて転送する)
+const TRANSCRIPT_MIN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// メッセージの文字数の上限
+const MAX_MESSAGE_CHARS: usize = 2000;
+
+/// 埋め込みのフィールドの文字数の上限
+const EMBED_FIELD_MAX_CHARS: usize = 1024;
+
+/// この時間以内に人間の発言があったスレッドは、メッセージを取得せずに会話があったものとみなす
+const RECENT_HUMAN_MESSAGE_WINDOW: Duration = Duration::from_secs(30 * 60);
+
+/// セッションを終了する理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SessionEndReason {
+    /// VCが削除された
+    VcDeleted,
+    /// `/forceend` で終了された
+    ForceEnded,
+    /// VCのイベントが終わった
+    EventEnded,
+}
+
+impl SessionEndReason {
+    /// VC終了間際に投稿されたメッセージを待ってから後始末するか
+    /// VCが残ったまま終了する場合は、終了を指示した時点までのメッセージで判断する
+    fn waits_for_grace_period(self) -> bool {
+        self == SessionEndReason::VcDeleted
+    }
+}
+
+/// VC終了時のスレッドの後始末の方針
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FinalizationDecision {
+    /// 会話がなかったので議題メッセージとスレッドを削除する
+    Delete,
+    /// 人間の発言はないがBotの投稿が続いたので、議題メッセージを消して終了メッセージを新たに投稿する
+    Archive,
+    /// 議題メッセージを終了メッセージに書き換える
+    Edit,
+}
+
+/// スレッドのメッセージからVC終了時のスレッドの後始末の方針を決める
+/// `messages` は新しい順に最大 `finalize_scan_limit` 件取得したもので、それより古いメッセージは判断に含まれない
+fn decide_finalization(
+    messages: &[Message],
+    bot_id: UserId,
+    threshold: usize,
+) -> FinalizationDecision {
+    // 取得した範囲に人間の発言があれば、いつの発言でも会話があったものとみなす
+    let has_human_message = messages
+        .iter()
+        .any(|m| m.author.id != bot_id && !m.author.bot);
+    if has_human_message {
+        return FinalizationDecision::Edit;
+    }
+    // Botのメッセージがしきい値以下だったらスレッドを削除し、それより多ければ議題メッセージを投稿し直す
+    if messages.len() <= threshold {
+        FinalizationDecision::Delete
+    } else {
+        FinalizationDecision::Archive
+    }
+}
+
+/// VCの名前変更の結果
+enum RenameResult {
+    /// 名前を変更した
+    Renamed,
+    /// 名前を変更できなかった (利用者に表示する理由)
+    Rejected(String),
+}
+
+/// VCのセッション (VCとスレッドの対応)
+#[derive(Debug, Clone)]
+struct VcSession {
+    /// VCがあるサーバーのID
+    guild_id: GuildId,
+    /// VCのチャンネルID
+    vc_channel_id: ChannelId,
+    /// スレッドのチャンネルID
+    thread_channel_id: ChannelId,
+    /// VC作成時のメッセージ (外部から削除された場合はNone)
+    agenda_message: Option<Message>,
+    /// セッションの開始時刻
+    start_time: Instant,
+    /// セッション中にVCに参加したユーザー
+    participants: HashSet<UserId>,
+    /// 途中経過のメッセージのID (まだ投稿していない場合はNone)
+    summary_message_id: Option<MessageId>,
+    /// スレッドでの人間の発言数
+    message_count: usize,
+}
+
+/// VCのスレッドのライフサイクルの状態
+/// 記録がない状態から `Creating` → `Active` → `Finalizing` と進み、後始末が終わると記録から取り除く
+/// 状態の確認と変更は `sessions` のロックの中でまとめて行い、想定外の遷移(後始末中のVCへの参加など)は受け付けない
+#[derive(Debug, Clone, PartialEq)]
+enum ThreadState {
+    /// スレッドを作成中
+    Creating,
+    /// スレッドが作成され、VCが使われている
+    Active(VcSession),
+    /// VCが終了し、スレッドを後始末している
+    Finalizing(VcSession),
+}
+
+impl ThreadState {
+    /// 進行中のセッション
+    fn active(&self) -> Option<&VcSession> {
+        match self {
+            ThreadState::Active(session) => Some(session),
+            _ => None,
+        }
+    }
+
+    /// 進行中のセッション (変更用)
+    fn active_mut(&mut self) -> Option<&mut VcSession> {
+        match self {
+            ThreadState::Active(session) => Some(session),
+            _ => None,
+        }
+    }
+
+    /// 進行中または後始末中のセッション
+    fn session(&self) -> Option<&VcSession> {
+        match self {
+            ThreadState::Active(session) | ThreadState::Finalizing(session) => Some(session),
+            ThreadState::Creating => None,
+        }
+    }
+
+    /// 進行中または後始末中のセッション (変更用)
+    /// VC終了直後の猶予期間中に投稿されたメッセージも数えられるようにする
+    fn session_mut(&mut self) -> Option<&mut VcSession> {
+        match self {
+            ThreadState::Active(session) | ThreadState::Finalizing(session) => Some(session),
+            ThreadState::Creating => None,
+        }
+    }
+}
+
+/// 議題メッセージは内容ではなくIDで比較する (テストでセッションを比較できるようにする)
+impl PartialEq for VcSession {
+    fn eq(&self, other: &Self) -> bool {
+        self.guild_id == other.guild_id
+            && self.vc_channel_id == other.vc_channel_id
+            && self.thread_channel_id == other.thread_channel_id
+            && self.agenda_message.as_ref().map(|m| m.id)
+                == other.agenda_message.as_ref().map(|m| m.id)
+            && self.start_time == other.start_time
+            && self.participants == other.participants
+            && self.summary_message_id == other.summary_message_id
+            && self.message_count == other.message_count
+    }
+}
+
+/// 埋め込みのフィールドの文字数の上限に収まるよう切り詰める
+fn truncate_field(text: String) -> String {
+    if text.chars().count() <= EMBED_FIELD_MAX_CHARS {
+        return text;
+    }
+    let mut text = text
+        .chars()
+        .take(EMBED_FIELD_MAX_CHARS - 1)
+        .collect::<String>();
+    text.push('…');
+    text
+}
+
+/// 議題メッセージの本文に現在の参加人数を付け加える
+/// 既に人数が書かれている場合は置き換える
+fn with_member_count(content: &str, member_count: usize) -> String {
+    let body = match content.rsplit_once('\n') {
+        Some((body, last_line)) if last_line.starts_with("現在") => body,
+        _ => content,
+    };
+    format!("{}\n現在{}人", body, member_count)
+}
+
+/// 議題メッセージの作成に使うnonceを、VCとセッションの開始時刻から決定的に作る
+/// 再送しても同じnonceになるため、Discord側で重複した送信が同じメッセージにまとめられる
+fn creation_nonce(vc_channel_id: &ChannelId, started_at: DateTime<Utc>) -> String {
+    let name = format!("{}:{}", vc_channel_id, started_at.timestamp_millis());
+    let uuid = uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_OID, name.as_bytes());
+    // nonceは25文字までなので、UUIDの先頭を使う
+    uuid.simple().to_string()[..MESSAGE_NONCE_MAX_CHARS].to_string()
+}
+
+/// serenity 0.11の`CreateMessage`にはnonceを設定するメソッドがないため、ビルダーに追加する
+trait CreateMessageNonceExt {
+    /// nonceを設定し、同じnonceのメッセージが既にあれば新たに作成しないようにする
+    fn enforced_nonce(&mut self, nonce: &str) -> &mut Self;
+}
+
+impl CreateMessageNonceExt for CreateMessage<'_> {
+    fn enforced_nonce(&mut self, nonce: &str) -> &mut Self {
+        self.0.insert("nonce", json!(nonce));
+        self.0.insert("enforce_nonce", json!(true));
+        self
+    }
+}
+
+/// 行を改行でつなぎ、1件が上限の文字数を超えないように分けたメッセージにする
+/// (1行が上限を超えないことを前提とする)
+fn batch_lines(lines: &[String], max_chars: usize) -> Vec<String> {
+    let mut batches = vec![];
+    let mut current = String::new();
+    let mut current_chars = 0;
+    for line in lines {
+        let line_chars = line.chars().count();
+        if !current.is_empty() && current_chars + 1 + line_chars > max_chars {
+            batches.push(std::mem::take(&mut current));
+            current_chars = 0;
+        }
+        if !current.is_empty() {
+            current.push('\n');
+            current_chars += 1;
+        }
+        current.push_str(line);
+        current_chars += line_chars;
+    }
+    if !current.is_empty() {
+        batches.push(current);
+    }
+    batches
+}
+
+/// 同時に管理するVCの上限の判定に数えるセッションの数 (作成中・後始末中も含め、指定したVC自身は除く)
+fn sessions_toward_cap(sessions: &IndexMap<ChannelId, ThreadState>, own: &ChannelId) -> usize {
+    sessions.keys().filter(|vc| *vc != own).count()
+}
+
+/// 上限を超えないよう古いものから取り除いてからマップに挿入し、取り除いたエントリを返す
+fn insert_bounded<K: Hash + Eq, V>(
+    map: &mut IndexMap<K, V>,
+    key: K,
+    value: V,
+    max_size: Option<usize>,
+) -> Vec<(K, V)> {
+    let mut evicted = Vec::new();
+    if let Some(max_size) = max_size {
+        while !map.contains_key(&key) && map.len() >= max_size.max(1) {
+            match map.shift_remove_index(0) {
+                Some(entry) => evicted.push(entry),
+                None => break,
+            }
+        }
+    }
+    map.insert(key, value);
+    evicted
+}
+
+/// サーバーのニックネームがあればニックネーム、なければ表示名(global_name)、どちらもなければユーザー名を返す
+fn display_name(nick: Option<&str>, user: &User) -> String {
+    nick.or(user.global_name.as_deref())
+        .unwrap_or(&user.name)
+        .to_string()
+}
+
+/// 会話の流れで表示するメンバーの名前を取得する (通知を飛ばさないよう、メンションではなく表示名を使う)
+fn display_name_or_mention(member: &Member) -> String {
+    display_name(member.nick.as_deref(), &member.user)
+}
+
+/// ユーザーの表示名を取得する
+/// 表示名(global_name)があれば表示名、なければユーザー名を返し、取得できなければメンションにする
+async fn format_user(user_id: UserId, ctx: &Context) -> String {
+    let user = match ctx.cache.user(user_id) {
+        Some(user) => Ok(user),
+        None => ctx.http.get_user(user_id.0).await,
+    };
+    match user {
+        Ok(user) => user
+            .global_name
+            .as_deref()
+            .unwrap_or(&user.name)
+            .to_string(),
+        Err(why) => {
+            warn!("ユーザーの取得に失敗: {:?}", why);
+            user_id.mention().to_string()
+        }
+    }
+}
+
+/// ダイアログの入力欄に入力された値を取得する
+fn modal_input_value(interaction: &ModalSubmitInteraction, custom_id: &str) -> Option<String> {
+    interaction
+        .data
+        .components
+        .iter()
+        .flat_map(|c| c.components.iter())
+        .find_map(|c| match c {
+            ActionRowComponent::InputText(t) if t.custom_id == custom_id => Some(t.value.clone()),
+            _ => None,
+        })
+}
+
+/// VCを移動していない(ミュートやスピーカーミュートの切り替えなどだけの)ボイス状態の更新か
+fn is_same_channel_update(old: Option<&VoiceState>, new: &VoiceState) -> bool {
+    old.and_then(|s| s.channel_id) == new.channel_id
+}
+
+/// 参加メッセージに添えるミュート状態 (キャッシュのボイス状態から判定する)
+fn mute_status_suffix(ctx: &Context, member: &Member) -> &'static str {
+    let state = ctx.cache.guild_field(member.guild_id, |guild| {
+        guild
+            .voice_states
+            .get(&member.user.id)
+            .map(|v| (v.self_deaf, v.self_mute))
+    });
+    match state.flatten() {
+        Some((true, _)) => "（スピーカーミュート中）",
+        Some((false, true)) => "（自分をミュート中）",
+        _ => "",
+    }
+}
+
+/// 終了メッセージに、スレッドを再開するボタンを付ける
+fn resume_button(c: &mut CreateComponents) -> &mut CreateComponents {
+    c.create_action_row(|f| {
+        f.create_button(|b| {
+            b.label("▶️再開する");
+            b.style(ButtonStyle::Secondary);
+            b.custom_id(RESUME_BUTTON_ID);
+            b
+        });
+        f
+    });
+    c
+}
+
+/// 再開ボタンが押されたときに再開するスレッドを決める
+/// 親チャンネルの議題メッセージに付いたボタンの場合、スレッドIDはメッセージIDと同じになる
+fn resume_target_thread(
+    channel_id: ChannelId,
+    channel_is_thread: bool,
+    message_id: MessageId,
+) -> ChannelId {
+    if channel_is_thread {
+        channel_id
+    } else {
+        ChannelId(message_id.0)
+    }
+}
+
+/// スレッドを作成するチャンネルがテキストチャンネルかフォーラムチャンネルかチェックする
+async fn validate_thread_channel_kind(ctx: &Context, thread_channel_id: ChannelId) -> Result<()> {
+    let thread_channel = get_channel(ctx, thread_channel_id)
+        .await
+        .with_context(|| format!("チャンネル({})の取得に失敗", thread_channel_id))?
+        .guild()
+        .ok_or(anyhow::anyhow!(
+            "チャンネル({})はサーバーのチャンネルではありません",
+            thread_channel_id
+        ))?;
+    match thread_channel.kind {
+        ChannelType::Text | ChannelType::Forum => Ok(()),
+        kind => anyhow::bail!(
+            "チャンネル({})はテキストチャンネルかフォーラムチャンネルである必要があります (種類: {})",
+            thread_channel_id,
+            kind.name()
+        ),
+    }
+}
+
+/// スレッドをアーカイブする (必要であればロックもする)
+async fn archive_thread(ctx: &Context, thread_channel_id: &ChannelId, lock: bool) -> Result<()> {
+    thread_channel_id
+        .edit_thread(ctx, |t| {
+            t.archived(true);
+            if lock {
+                t.locked(true);
+            }
+            t
+        })
+        .await
+        .context("VCスレッドチャンネルのアーカイブに失敗")?;
+    Ok(())
+}
+
+/// 無視するVCの名前のパターンをコンパイルする
+/// `regex-name-patterns` featureが有効な場合は正規表現として、無効な場合は部分一致として扱う (どちらも大文字小文字は区別しない)
+fn compile_name_patterns(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            let pattern = if cfg!(feature = "regex-name-patterns") {
+                pattern.clone()
+            } else {
+                regex::escape(pattern)
+            };
+            RegexBuilder::new(&pattern)
+                .case_insensitive(true)
+                .build()
+                .with_context(|| format!("VCの名前のパターンが不正です: {}", pattern))
+        })
+        .collect()
+}
+
+/// チャンネルを取得する
+/// キャッシュにあればキャッシュから取得し、なければAPIから取得する
+async fn get_channel(ctx: &Context, channel_id: ChannelId) -> serenity::Result<Channel> {
+    match ctx.cache.channel(channel_id) {
+        Some(channel) => Ok(channel),
+        None => channel_id.to_channel(ctx).await,
+    }
+}
+
+/// ハンドラーが担当するサーバー
+pub enum GuildScope {
+    /// 指定したサーバー以外の全て (専用のトークンを持たないサーバー)
+    AllExcept(HashSet<GuildId>),
+    /// 指定したサーバーのみ
+    Only(HashSet<GuildId>),
+}
+
+/// インタラクションが発生したサーバーを取得する
+fn interaction_guild_id(interaction: &Interaction) -> Option<GuildId> {
+    match interaction {
+        Interaction::MessageComponent(interaction) => interaction.guild_id,
+        Interaction::ModalSubmit(interaction) => interaction.guild_id,
+        Interaction::ApplicationCommand(interaction) => interaction.guild_id,
+        Interaction::Autocomplete(interaction) => interaction.guild_id,
+        _ => None,
+    }
+}
 
 /// イベント受信リスナー
+/// シャードごとに複製しても状態を共有できるよう、内部の状態は全てArcで保持する
+#[derive(Clone)]
 pub struct Handler {
     /// Bot
-    bot_user_id: Mutex<Option<UserId>>,
+    bot_user_id: Arc<OnceCell<UserId>>,
+    /// 準備完了したか
+    ready_complete: Arc<Mutex<bool>>,
+    /// 準備完了前に届いたVC参加/退出イベント
+    pending_events: Arc<Mutex<VecDeque<(Option<VoiceState>, VoiceState)>>>,
     /// 設定
-    app_config: AppConfig,
-    /// VC→スレッドのマップ
-    vc_to_thread: Mutex<HashMap<ChannelId, ChannelId>>,
-    /// スレッド→VCのマップ
-    thread_to_vc: Mutex<HashMap<ChannelId, ChannelId>>,
-    /// スレッド→VC作成時のメッセージのIDのマップ
-    thread_to_agenda_message: Mutex<HashMap<ChannelId, Message>>,
+    app_config: Arc<AppConfig>,
+    /// 担当するサーバー
+    guild_scope: Arc<GuildScope>,
+    /// 無視するチャンネル (`vc_ignored_channels_file` が変更されたら読み込み直す)
+    vc_ignored_channels: Arc<std::sync::RwLock<HashSet<ChannelId>>>,
+    /// 無視するVCの名前のパターン
+    vc_ignored_name_patterns: Arc<Vec<Regex>>,
+    /// VC→セッションのマップ
+    sessions: Arc<Mutex<IndexMap<ChannelId, ThreadState>>>,
+    /// VC→最初に参加したユーザーのマップ
+    vc_session_creator: Arc<Mutex<HashMap<ChannelId, UserId>>>,
+    /// VC→現在VCにいるメンバーのマップ
+    vc_to_members: Arc<Mutex<HashMap<ChannelId, HashSet<UserId>>>>,
+    /// 更新を予約しているスレッド
+    topic_update_pending: Arc<Mutex<HashSet<ChannelId>>>,
+    /// スレッドの更新処理を開始したか
+    thread_update_task_started: Arc<AtomicBool>,
+    /// 起動時の設定チェックに失敗したか (失敗した場合はイベントを処理しない)
+    startup_failed: Arc<AtomicBool>,
+    /// VC→名前変更の履歴(変更前の名前, 変更したユーザー, 変更時刻)のマップ (古い順)
+    vc_rename_history: Arc<Mutex<HashMap<ChannelId, VecDeque<(String, UserId, DateTime<Utc>)>>>>,
+    /// VC→このセッションで名前を変更した回数のマップ
+    vc_rename_count: Arc<Mutex<HashMap<ChannelId, usize>>>,
+    /// 満員に近いことを警告したVC
+    warned_channels: Arc<Mutex<HashSet<ChannelId>>>,
+    /// 同時に管理するVCの上限に達したことを通知したVC (セッションが終わって空きができたら再び通知する)
+    session_cap_warned_channels: Arc<Mutex<HashSet<ChannelId>>>,
+    /// 同時にスレッドを作成できる数 (待っている作成は来た順に処理される)
+    thread_creation_permits: Arc<Semaphore>,
+    /// 人数が集まるまでスレッドの作成を保留しているVC
+    vc_pending: Arc<Mutex<HashSet<ChannelId>>>,
+    /// スレッド→最後に人間が発言した時刻のマップ
+    thread_last_human_message: Arc<Mutex<HashMap<ChannelId, Instant>>>,
+    /// スレッド→ライブ転送を待っている発言のマップ
+    transcript_queue: Arc<Mutex<IndexMap<ChannelId, Vec<String>>>>,
+    /// VC→スレッド名に表示している人数のマップ
+    thread_name_member_counts: Arc<Mutex<HashMap<ChannelId, usize>>>,
+    /// VC→最後にスレッドを作成した時刻のマップ
+    vc_last_thread_created: Arc<Mutex<HashMap<ChannelId, Instant>>>,
+    /// スレッド→名前変更ボタン付きの参加メッセージのIDのマップ
+    thread_to_welcome_message: Arc<Mutex<IndexMap<ChannelId, MessageId>>>,
+    /// VC→`/pinmessage` でピン留めしたメッセージのマップ (セッションの終了時にピン留めを外す)
+    vc_pinned_messages: Arc<Mutex<HashMap<ChannelId, Vec<MessageId>>>>,
+    /// 起動中に `/botconfig` で変更できる設定
+    bot_config: Arc<RwLock<BotConfig>>,
+    /// VC→`/setvcgreeting` で設定された参加メッセージのマップ (VCが削除されるまで残す)
+    vc_custom_greetings: Arc<Mutex<HashMap<ChannelId, String>>>,
+    /// イベント→イベントのためにスレッドを作成したVCのマップ
+    event_sessions: Arc<Mutex<HashMap<ScheduledEventId, ChannelId>>>,
+    /// VC→セッション中に動かしているタスク(途中経過の投稿など)のマップ (セッションの終了時に中断する)
+    session_tasks: Arc<Mutex<HashMap<ChannelId, Vec<JoinHandle<()>>>>>,
+    /// 起動時刻
+    start_time: Instant,
+    /// 作成したスレッドの数
+    threads_created: Arc<AtomicU64>,
+    /// VCの名前を変更した回数
+    renames_performed: Arc<AtomicU64>,
+    /// 会話があって終了したセッションの数
+    sessions_ended_with_messages: Arc<AtomicU64>,
+    /// 会話がなく終了したセッションの数
+    sessions_ended_without_messages: Arc<AtomicU64>,
+    /// 後始末した孤立スレッドの数
+    orphaned_threads_cleaned: Arc<AtomicU64>,
+    /// Discord APIの呼び出しに失敗した回数
+    api_errors: Arc<AtomicU64>,
+}
+
+/// `/metrics` で公開する統計
+pub struct Metrics {
+    /// 作成したセッション(スレッド)の数
+    pub sessions_created: u64,
+    /// 終了したセッションの数
+    pub sessions_ended: u64,
+    /// VCの名前を変更した回数
+    pub renames: u64,
+    /// 管理中のセッションの数
+    pub active_sessions: u64,
+    /// Discord APIの呼び出しに失敗した回数
+    pub api_errors: u64,
 }
 
 impl Handler {
     /// コンストラクタ
     pub fn new(app_config: AppConfig) -> Result<Self> {
+        let vc_ignored_channels = ignored_channels::load_ignored_channels(&app_config.discord)?;
+        let vc_ignored_name_patterns =
+            compile_name_patterns(&app_config.discord.vc_ignored_name_patterns)?;
+        let bot_config = BotConfig::new(&app_config.discord);
+        let max_thread_creations = app_config.discord.max_concurrent_thread_creations;
         Ok(Self {
-            bot_user_id: Mutex::new(None),
-            app_config,
-            vc_to_thread: Mutex::new(HashMap::new()),
-            thread_to_vc: Mutex::new(HashMap::new()),
-            thread_to_agenda_message: Mutex::new(HashMap::new()),
+            bot_user_id: Arc::new(OnceCell::new()),
+            ready_complete: Arc::new(Mutex::new(false)),
+            pending_events: Arc::new(Mutex::new(VecDeque::new())),
+            app_config: Arc::new(app_config),
+            guild_scope: Arc::new(GuildScope::AllExcept(HashSet::new())),
+            vc_ignored_channels: Arc::new(std::sync::RwLock::new(vc_ignored_channels)),
+            vc_ignored_name_patterns: Arc::new(vc_ignored_name_patterns),
+            sessions: Arc::new(Mutex::new(IndexMap::new())),
+            vc_session_creator: Arc::new(Mutex::new(HashMap::new())),
+            vc_to_members: Arc::new(Mutex::new(HashMap::new())),
+            topic_update_pending: Arc::new(Mutex::new(HashSet::new())),
+            thread_update_task_started: Arc::new(AtomicBool::new(false)),
+            startup_failed: Arc::new(AtomicBool::new(false)),
+            vc_rename_history: Arc::new(Mutex::new(HashMap::new())),
+            vc_pending: Arc::new(Mutex::new(HashSet::new())),
+            thread_creation_permits: Arc::new(Semaphore::new(max_thread_creations)),
+            vc_rename_count: Arc::new(Mutex::new(HashMap::new())),
+            warned_channels: Arc::new(Mutex::new(HashSet::new())),
+            session_cap_warned_channels: Arc::new(Mutex::new(HashSet::new())),
+            vc_last_thread_created: Arc::new(Mutex::new(HashMap::new())),
+            thread_name_member_counts: Arc::new(Mutex::new(HashMap::new())),
+            thread_last_human_message: Arc::new(Mutex::new(HashMap::new())),
+            transcript_queue: Arc::new(Mutex::new(IndexMap::new())),
+            thread_to_welcome_message: Arc::new(Mutex::new(IndexMap::new())),
+            session_tasks: Arc::new(Mutex::new(HashMap::new())),
+            event_sessions: Arc::new(Mutex::new(HashMap::new())),
+            vc_custom_greetings: Arc::new(Mutex::new(HashMap::new())),
+            vc_pinned_messages: Arc::new(Mutex::new(HashMap::new())),
+            bot_config: Arc::new(RwLock::new(bot_config)),
+            start_time: Instant::now(),
+            threads_created: Arc::new(AtomicU64::new(0)),
+            renames_performed: Arc::new(AtomicU64::new(0)),
+            sessions_ended_with_messages: Arc::new(AtomicU64::new(0)),
+            sessions_ended_without_messages: Arc::new(AtomicU64::new(0)),
+            orphaned_threads_cleaned: Arc::new(AtomicU64::new(0)),
+            api_errors: Arc::new(AtomicU64::new(0)),
         })
     }
 
+    /// 担当するサーバーのスレッドを作成するチャンネル(VCごとの指定を含む)がテキストチャンネルかフォーラムチャンネルかチェックする
+    async fn validate_thread_channel(&self, ctx: &Context) -> Result<()> {
+        let discord = &self.app_config.discord;
+        // 担当するサーバーごとのチャンネル
+        let mut thread_channels = discord
+            .guild_channels
+            .iter()
+            .filter(|(guild_id, _)| self.handles_guild(Some(**guild_id)))
+            .map(|(_, channels)| channels.thread_channel)
+            .collect::<Vec<_>>();
+        // 既定のチャンネルは、専用のトークンを使わないハンドラーからのみ見える
+        if let GuildScope::AllExcept(_) = *self.guild_scope {
+            thread_channels.push(discord.thread_channel);
+            thread_channels.extend(discord.vc_thread_channel_overrides.values());
+        }
+        for thread_channel_id in thread_channels {
+            validate_thread_channel_kind(ctx, thread_channel_id).await?;
+        }
+        Ok(())
+    }
+
+    /// 担当するサーバーを指定したハンドラーを作成する
+    /// VCの記録などの状態は共有し、接続ごとの状態(準備完了やBotのユーザーなど)は新たに作る
+    pub fn with_guild_scope(&self, guild_scope: GuildScope) -> Self {
+        Self {
+            guild_scope: Arc::new(guild_scope),
+            bot_user_id: Arc::new(OnceCell::new()),
+            ready_complete: Arc::new(Mutex::new(false)),
+            pending_events: Arc::new(Mutex::new(VecDeque::new())),
+            topic_update_pending: Arc::new(Mutex::new(HashSet::new())),
+            thread_update_task_started: Arc::new(AtomicBool::new(false)),
+            startup_failed: Arc::new(AtomicBool::new(false)),
+            ..self.clone()
+        }
+    }
+
+    /// 担当するサーバーか判定する
+    fn handles_guild(&self, guild_id: Option<GuildId>) -> bool {
+        match (&*self.guild_scope, guild_id) {
+            (GuildScope::AllExcept(excluded), Some(guild_id)) => !excluded.contains(&guild_id),
+            (GuildScope::AllExcept(_), None) => true,
+            (GuildScope::Only(guilds), Some(guild_id)) => guilds.contains(&guild_id),
+            (GuildScope::Only(_), None) => false,
+        }
+    }
+
+    /// 起動時の設定チェックに失敗したか
+    fn is_startup_failed(&self) -> bool {
+        self.startup_failed.load(Ordering::SeqCst)
+    }
+
     /// カスタムVCかどうか判定する
     fn is_custom_vc(&self, channel: &GuildChannel) -> bool {
-        // チャンネルがVCでない場合は無視
-        if channel.kind != ChannelType::Voice {
+        // チャンネルがVCでない場合は無視 (設定で有効な場合はステージチャンネルも含む)
+        if !is_voice_like(channel.kind, self.app_config.discord.manage_stage_channels) {
             return false;
         }
 
@@ -65,23 +750,48 @@ impl Handler {
         };
 
         // 親チャンネルIDがカスタムVCカテゴリかどうか判定
-        if parent_channel_id != self.app_config.discord.vc_category {
+        if parent_channel_id != self.app_config.discord.vc_category_for(channel.guild_id) {
             return false;
         }
 
         // チャンネルが無視されるチャンネルかどうか判定
         if self
-            .app_config
-            .discord
             .vc_ignored_channels
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
             .contains(&channel.id)
         {
             return false;
         }
 
+        // チャンネル名が無視するパターンに一致するか判定
+        if self
+            .vc_ignored_name_patterns
+            .iter()
+            .any(|pattern| pattern.is_match(&channel.name))
+        {
+            return false;
+        }
+
         true
     }
 
+    /// 参加しても無視するユーザーか判定する
+    fn is_ignored_member(&self, member: &Member) -> bool {
+        // 無視するユーザーに指定されているか判定
+        if self
+            .app_config
+            .discord
+            .vc_ignored_user_ids
+            .contains(&member.user.id)
+        {
+            return true;
+        }
+
+        // Botを無視する設定の場合はBotか判定
+        self.app_config.discord.ignore_bots && member.user.bot
+    }
+
     /// 参加時にスレッドを作成する
     async fn create_or_mention_thread(
         &self,
@@ -89,33 +799,104 @@ impl Handler {
         vc_channel_id: &ChannelId,
         member: &Member,
     ) -> Result<()> {
-        // マップからスレッドのチャンネルIDを取得
-        let map = self
-            .vc_to_thread
+        self.create_or_mention_thread_named(ctx, vc_channel_id, member, None)
+            .await
+    }
+
+    /// スレッドを作成するか、作成済みであれば参加メッセージを送る
+    /// スレッドを作成する場合、名前が指定されていればVCの名前の代わりにスレッド名に使う
+    async fn create_or_mention_thread_named(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+        member: &Member,
+        name_override: Option<&str>,
+    ) -> Result<()> {
+        // 最初に参加したユーザーを記録し、本人が作成者かどうか判定
+        let is_creator = *self
+            .vc_session_creator
             .lock()
             .await
-            .get(vc_channel_id)
-            .map(|c| c.clone());
-        // 一度変数に入れてからmatchにいれないとロックされっぱなしになる
+            .entry(*vc_channel_id)
+            .or_insert(member.user.id)
+            == member.user.id;
+
+        // マップからスレッドのチャンネルIDを取得
+        // 他のイベントでスレッドを作成中の場合は、作成が終わるまで待ってから確認し直す
+        let mut retries = 0;
+        let map = loop {
+            {
+                let mut sessions = self.sessions.lock().await;
+                match sessions.get(vc_channel_id) {
+                    // スレッドが作成済みならそのまま進む
+                    Some(ThreadState::Active(session)) => break Some(session.thread_channel_id),
+                    // 後始末中のVCには参加しない (VCの削除と同時に参加した場合など)
+                    Some(ThreadState::Finalizing(_)) => {
+                        debug!(
+                            "VC({})は後始末中のため、参加の処理を中断します",
+                            vc_channel_id
+                        );
+                        return Ok(());
+                    }
+                    Some(ThreadState::Creating) => {}
+                    // 誰も作成中でなければ、自分が作成中にして進む
+                    None => {
+                        sessions.insert(*vc_channel_id, ThreadState::Creating);
+                        break None;
+                    }
+                }
+            }
+            // 待っても作成が終わらない場合は、作成中のイベントに任せる
+            retries += 1;
+            if retries > THREAD_CREATION_MAX_RETRIES {
+                warn!(
+                    "VC({})のスレッドの作成が終わらないため、参加の処理を中断します",
+                    vc_channel_id
+                );
+                return Ok(());
+            }
+            sleep(THREAD_CREATION_RETRY_INTERVAL).await;
+        };
         match map {
             // スレッドが作成済みの場合
             Some(thread_id) => {
-                // スレッドのメンバーを取得
-                let members = thread_id
-                    .get_thread_members(ctx)
+                // VCのメンバーに追加し、新たに参加した場合のみメッセージを送る
+                let is_new_member = self
+                    .vc_to_members
+                    .lock()
                     .await
-                    .context("スレッドメンバーの取得に失敗")?;
-                // メンバーが存在しない場合
-                if !members
-                    .iter()
-                    .filter_map(|m| m.user_id)
-                    .any(|user_id| user_id == member.user.id)
-                {
-                    // 参加メッセージ
+                    .entry(*vc_channel_id)
+                    .or_default()
+                    .insert(member.user.id);
+                if is_new_member {
+                    // セッションの参加者に追加
+                    if let Some(session) = self
+                        .sessions
+                        .lock()
+                        .await
+                        .get_mut(vc_channel_id)
+                        .and_then(ThreadState::active_mut)
+                    {
+                        session.participants.insert(member.user.id);
+                    }
+
+                    // 参加メッセージ (設定で有効な場合はミュート状態も添える)
+                    let suffix = if self.app_config.discord.show_mute_status {
+                        mute_status_suffix(ctx, member)
+                    } else {
+                        ""
+                    };
                     thread_id
                         .send_message(ctx, |m| {
-                            m.content(format!("{} さんが参加しました。", member.mention()));
-                            m
+                            self.set_formatted_content(
+                                m,
+                                format!(
+                                    "{} さんが参加しました。{}",
+                                    display_name_or_mention(member),
+                                    suffix
+                                ),
+                                None,
+                            )
                         })
                         .await
                         .context("参加メッセージの送信に失敗")?;
@@ -123,159 +904,1605 @@ impl Handler {
             }
             // スレッドが作成されていない場合
             None => {
-                // チャンネル名を取得
-                let channel_name = vc_channel_id
-                    .name(&ctx)
-                    .await
-                    .unwrap_or("不明なVC".to_string());
-                // VCカテゴリチャンネルにメッセージを送信
-                let thread_channel = self.app_config.discord.thread_channel;
-                // 議題メッセージを送信
-                let message = thread_channel
-                    .send_message(ctx, |m| {
-                        m.content(format!(
-                            "{} さんが新しいVCを作成しました。\nVCに参加する→ {}",
-                            member.mention(),
-                            vc_channel_id.mention(),
-                        ));
-                        m.allowed_mentions(|m| m.empty_users());
-                        m
-                    })
-                    .await
-                    .context("議題メッセージの送信に失敗")?;
-                // スレッドを作成
-                let thread = thread_channel
-                    .create_public_thread(ctx, &message, |m| {
-                        m.name(&channel_name);
-                        m.kind(ChannelType::PublicThread);
-                        m
-                    })
-                    .await
-                    .context("スレッドの作成に失敗")?;
-                // VCのテキストにチャンネルメンションを追加
-                vc_channel_id
-                    .send_message(ctx, |m| {
-                        m.content(format!("VCチャット→ {}", thread.mention()));
-                        m
-                    })
-                    .await
-                    .context("VCチャットの案内メッセージ作成に失敗")?;
-                // 参加メッセージ
-                thread
-                    .send_message(ctx, |m| {
-                        m.content(format!("{} `{}`へようこそ。\n興味を引くチャンネル名に変えてみんなを呼び込もう！", member.mention(), &channel_name));
-                        m.components(|c| {
-                            c.create_action_row(|f| {
-                                f.create_button(|b| {
-                                    b.label("📝チャンネル名を変える");
-                                    b.style(ButtonStyle::Success);
-                                    b.custom_id("rename_button");
-                                    b
-                                });
-                                f
-                            });
-                            c
-                        });
-                        m
-                    })
-                    .await
-                    .context("参加メッセージの作成に失敗")?;
-
-                // スレッドID->VCを登録
-                self.thread_to_vc
-                    .lock()
-                    .await
-                    .insert(thread.id, vc_channel_id.clone());
-
-                // チャンネルID->スレッドを登録
-                self.vc_to_thread
-                    .lock()
-                    .await
-                    .insert(vc_channel_id.clone(), thread.id);
-
-                // チャンネルID->スレッドを登録
-                self.thread_to_agenda_message
-                    .lock()
-                    .await
-                    .insert(thread.id, message);
+                // スレッドを作成し、成否に関わらず作成中の印を外す
+                // 議題メッセージのnonceが順番待ちや再送で変わらないよう、開始時刻は最初に一度だけ決める
+                let started_at = Utc::now();
+                // 多くのVCが一斉に始まった時にレートリミットにかからないよう、同時に作成する数を制限する
+                let result = match self.thread_creation_permits.acquire().await {
+                    Ok(_permit) => {
+                        self.create_thread(
+                            ctx,
+                            vc_channel_id,
+                            member,
+                            is_creator,
+                            name_override,
+                            started_at,
+                        )
+                        .await
+                    }
+                    Err(why) => Err(why).context("スレッドの作成の順番待ちに失敗"),
+                };
+                {
+                    // 作成しなかった場合(上限到達や失敗)は、作成中の記録を取り除く
+                    let mut sessions = self.sessions.lock().await;
+                    if sessions.get(vc_channel_id) == Some(&ThreadState::Creating) {
+                        sessions.shift_remove(vc_channel_id);
+                    }
+                }
+                result?;
             }
         };
 
         Ok(())
     }
 
-    /// VC名前変更時にスレッドをリネームする
-    async fn rename_thread(&self, ctx: &Context, vc_channel_id: &ChannelId) -> Result<()> {
-        // マップからスレッドのチャンネルIDを取得
-        let channel_id = self
-            .vc_to_thread
-            .lock()
-            .await
-            .get(vc_channel_id)
-            .map(|c| c.clone());
-        // 一度変数に入れてからmatchにいれないとロックされっぱなしになる
-        match channel_id {
-            // スレッドが作成済みの場合
-            Some(thread_id) => {
-                // チャンネル名を取得
-                let channel_name = vc_channel_id
-                    .name(&ctx)
-                    .await
-                    .unwrap_or("不明なVC".to_string());
-                // スレッドをリネーム
-                thread_id
-                    .edit_thread(ctx, |t| {
-                        t.name(channel_name);
-                        t
-                    })
-                    .await
-                    .context("スレッドのリネームに失敗")?;
+    /// VCのスレッドを作成する
+    async fn create_thread(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+        member: &Member,
+        is_creator: bool,
+        name_override: Option<&str>,
+        started_at: DateTime<Utc>,
+    ) -> Result<()> {
+        // 同時に管理するVCの上限に達している場合は作成しない (他のVCで作成中のものも数え、自分自身は数えない)
+        let session_count = sessions_toward_cap(&*self.sessions.lock().await, vc_channel_id);
+        let max_sessions = self.app_config.discord.max_concurrent_sessions;
+        if session_count >= max_sessions {
+            // 参加のたびに通知が溢れないよう、空きができるまではVCごとに1回だけ通知する
+            if !self
+                .session_cap_warned_channels
+                .lock()
+                .await
+                .insert(*vc_channel_id)
+            {
+                return Ok(());
             }
-            // スレッドが作成されていない場合
-            None => {}
-        };
-
-        Ok(())
-    }
+            warn!(
+                "同時に管理するVCの上限({})に達したため、スレッドを作成しません",
+                max_sessions
+            );
+            vc_channel_id
+                .send_message(ctx, |m| {
+                    m.content("⚠️同時に作成できるVCチャットの上限に達しているため、スレッドを作成できませんでした");
+                    m
+                })
+                .await
+                .context("上限到達メッセージの送信に失敗")?;
+            return Ok(());
+        }
 
-    /// VCを取得
-    async fn get_vc(&self, ctx: &Context, channel_id: &ChannelId) -> Result<GuildChannel> {
-        // マップからスレッドのチャンネルIDを取得
-        // 一度変数に入れてからmatchにいれないとロックされっぱなしになる
-        let vc_channel_id = self
-            .thread_to_vc
+        // 直前にスレッドを作成したばかりのVCでは作成しない (入退出を繰り返してスレッドが量産されるのを防ぐ)
+        let cooldown = Duration::from_secs(self.app_config.discord.thread_creation_cooldown_secs);
+        if let Some(last_created) = self
+            .vc_last_thread_created
             .lock()
             .await
-            .get(channel_id)
-            .map(|c| c.clone())
-            .ok_or(anyhow::anyhow!("無効なVCチャンネル"))?;
-        let vc_channel = vc_channel_id
-            .to_channel(&ctx)
-            .await
-            .context("チャンネルの取得に失敗")?;
-        let vc_channel = vc_channel
+            .get(vc_channel_id)
+            .filter(|last_created| last_created.elapsed() < cooldown)
+        {
+            debug!(
+                "VC({})は{}秒前にスレッドを作成したばかりのため、スレッドを作成しません",
+                vc_channel_id,
+                last_created.elapsed().as_secs()
+            );
+            return Ok(());
+        }
+
+        // チャンネル名を取得 (イベントなどで名前が指定されていればそちらを使う)
+        let channel_name = match name_override {
+            Some(name) => name.to_string(),
+            None => vc_channel_id
+                .name(&ctx)
+                .await
+                .unwrap_or("不明なVC".to_string()),
+        };
+        // VCカテゴリチャンネルにメッセージを送信 (VCごとに指定されていればそのチャンネル)
+        let thread_channel = self.bot_config.read().await.thread_channel_for(
+            vc_channel_id,
+            self.app_config.discord.thread_channel_for(member.guild_id),
+        );
+        // VCの参加人数を取得 (取得できなければ参加した本人のみとみなす)
+        let member_count = self.vc_member_count(ctx, vc_channel_id).await.unwrap_or(1);
+        // 議題メッセージを送信 (再送で重複しないよう、決定的なnonceを付ける)
+        let nonce = creation_nonce(vc_channel_id, started_at);
+        let message = thread_channel
+            .send_message(ctx, |m| {
+                m.enforced_nonce(&nonce);
+                let content = if is_creator {
+                    format!(
+                        "{} さんが新しいVCを作成しました。\nVCに参加する→ {}",
+                        member.mention(),
+                        vc_channel_id.mention(),
+                    )
+                } else {
+                    format!(
+                        "{} さんがVCに参加しています。\nVCに参加する→ {}",
+                        member.mention(),
+                        vc_channel_id.mention(),
+                    )
+                };
+                m.content(with_member_count(&content, member_count));
+                m.allowed_mentions(|m| m.empty_users());
+                // 設定で有効な場合は、スレッドに参加していない人も押せるよう議題メッセージにもボタンを付ける
+                let discord = &self.app_config.discord;
+                if discord.rename_button_placement.in_agenda_message()
+                    && !discord.buttons.is_empty()
+                {
+                    m.components(|c| self.create_buttons(c));
+                }
+                m
+            })
+            .await
+            .context("議題メッセージの送信に失敗")?;
+        // スレッド名に表示した人数を記録
+        self.thread_name_member_counts
+            .lock()
+            .await
+            .insert(*vc_channel_id, member_count);
+        // 一覧で見分けられるよう、議題メッセージにリアクションを付ける
+        if let Some(reaction) = self.vc_creation_reaction() {
+            match message.react(ctx, reaction).await {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("議題メッセージへのリアクションに失敗: {:?}", why);
+                }
+            }
+        }
+        // スレッドを作成
+        let thread = self
+            .create_thread_from_message(
+                ctx,
+                &message,
+                &self.thread_name(&channel_name, member_count),
+            )
+            .await?;
+        self.threads_created.fetch_add(1, Ordering::Relaxed);
+        self.audit(
+            ctx,
+            AuditAction::ThreadCreated {
+                vc: *vc_channel_id,
+                thread: thread.id,
+                by: member.user.id,
+            },
+        )
+        .await;
+        // スレッドの作成時刻を記録 (クールダウンが過ぎた記録は削除する)
+        {
+            let mut last_created = self.vc_last_thread_created.lock().await;
+            last_created.retain(|_, created_at| created_at.elapsed() < cooldown);
+            last_created.insert(*vc_channel_id, Instant::now());
+        }
+        // 設定で有効な場合は、VCの参加者をスレッドのメンバーに追加する
+        if self.app_config.discord.auto_add_thread_members {
+            match self
+                .add_vc_members_to_thread(ctx, vc_channel_id, thread.id)
+                .await
+            {
+                Ok(_) => {}
+                Err(why) => {
+                    warn!("VCの参加者をスレッドに追加できませんでした: {:?}", why);
+                }
+            }
+        }
+        // VCのテキストにチャンネルメンションを追加 (失敗してもスレッドは作成済みなので続ける)
+        self.post_thread_link_to_vc(ctx, vc_channel_id, thread.id)
+            .await;
+        // 参加メッセージ (VCごとの参加メッセージ、`/botconfig` で設定された書式の順に優先する)
+        let template = self
+            .vc_custom_greetings
+            .lock()
+            .await
+            .get(vc_channel_id)
+            .cloned();
+        let template = match template {
+            Some(template) => Some(template),
+            None => self
+                .bot_config
+                .read()
+                .await
+                .welcome_message_template
+                .clone(),
+        };
+        let greeting = match template {
+            Some(template) => template
+                .replace("{user}", &member.mention().to_string())
+                .replace("{channel_name}", &channel_name),
+            None => format!(
+                "{} `{}`へようこそ。\n興味を引くチャンネル名に変えてみんなを呼び込もう！",
+                member.mention(),
+                &channel_name
+            ),
+        };
+        let welcome_message = self
+            .send_welcome_message(ctx, thread.id, greeting, Utc::now())
+            .await
+            .context("参加メッセージの作成に失敗")?;
+
+        // スレッドID->参加メッセージを登録
+        insert_bounded(
+            &mut *self.thread_to_welcome_message.lock().await,
+            thread.id,
+            welcome_message.id,
+            self.app_config.discord.max_map_size,
+        );
+
+        // VCのメンバーを登録
+        self.vc_to_members
+            .lock()
+            .await
+            .entry(*vc_channel_id)
+            .or_default()
+            .insert(member.user.id);
+
+        // セッションを登録
+        let evicted = insert_bounded(
+            &mut *self.sessions.lock().await,
+            *vc_channel_id,
+            ThreadState::Active(VcSession {
+                guild_id: member.guild_id,
+                vc_channel_id: *vc_channel_id,
+                thread_channel_id: thread.id,
+                agenda_message: Some(message),
+                start_time: Instant::now(),
+                participants: HashSet::from([member.user.id]),
+                summary_message_id: None,
+                message_count: 0,
+            }),
+            self.app_config.discord.max_map_size,
+        );
+        // 一定時間後に途中経過を投稿する
+        self.schedule_mid_session_summary(ctx, *vc_channel_id).await;
+        // 設定で有効な場合は、発言がない状態が続いたら声をかける
+        self.watch_inactivity(ctx, *vc_channel_id).await;
+        // 設定で有効な場合は、一定時間後に低速モードを解除する
+        self.schedule_slowmode_lift(ctx, *vc_channel_id, thread.id)
+            .await;
+
+        // 開始を通知
+        if self.app_config.webhook_on_start {
+            self.notify_webhook(json!({
+                "event": "session_start",
+                "vc_id": vc_channel_id.to_string(),
+                "thread_id": thread.id.to_string(),
+                "creator": member.user.id.to_string(),
+                "timestamp": Utc::now().to_rfc3339(),
+            }));
+        }
+
+        for (evicted_vc_id, evicted_state) in evicted {
+            // VCがまだ残っている場合は、スレッドが後始末されなくなるので警告する
+            if let (Some(_), Some(evicted_session)) = (
+                ctx.cache.guild_channel(evicted_vc_id),
+                evicted_state.session(),
+            ) {
+                warn!(
+                    "記録の上限に達したため、進行中のVCの記録を削除しました (スレッドは後始末されません): VC={}, スレッド={}",
+                    evicted_vc_id, evicted_session.thread_channel_id
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// VCの設定が変更されたことなどをスレッドに投稿する (セッションがなければ何もしない)
+    async fn post_to_session_thread(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+        content: String,
+    ) {
+        let session = match self.session_for_vc(vc_channel_id).await {
+            Some(session) => session,
+            None => return,
+        };
+        match session
+            .thread_channel_id
+            .send_message(ctx, |m| {
+                m.content(content);
+                m.allowed_mentions(|m| m.empty_parse());
+                m
+            })
+            .await
+        {
+            Ok(_) => {}
+            Err(why) => {
+                error!("VCの変更の投稿に失敗: {:?}", why);
+            }
+        }
+    }
+
+    /// 現在の統計を取得する
+    pub async fn metrics(&self) -> Metrics {
+        Metrics {
+            sessions_created: self.threads_created.load(Ordering::Relaxed),
+            sessions_ended: self.sessions_ended_with_messages.load(Ordering::Relaxed)
+                + self.sessions_ended_without_messages.load(Ordering::Relaxed),
+            renames: self.renames_performed.load(Ordering::Relaxed),
+            active_sessions: self.session_count().await as u64,
+            api_errors: self.api_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Discord APIの呼び出しに失敗したことによるエラーであれば数える
+    fn count_api_error(&self, why: &anyhow::Error) {
+        if why.chain().any(|cause| cause.is::<serenity::Error>()) {
+            self.api_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// 議題メッセージからスレッドを作成する
+    /// 応答を受け取る前に失敗した場合もスレッドが作成済みのことがあるため、再試行の前に確認して二重に作成しないようにする
+    async fn create_thread_from_message(
+        &self,
+        ctx: &Context,
+        message: &Message,
+        name: &str,
+    ) -> Result<GuildChannel> {
+        let auto_archive_minutes = self.bot_config.read().await.thread_auto_archive_minutes;
+        let mut attempt = 1;
+        loop {
+            let why = match message
+                .channel_id
+                .create_public_thread(ctx, message, |m| {
+                    m.name(name);
+                    m.kind(ChannelType::PublicThread);
+                    // 低速モードを設定
+                    if self.app_config.discord.thread_slowmode_seconds > 0 {
+                        m.rate_limit_per_user(
+                            self.app_config.discord.thread_slowmode_seconds.into(),
+                        );
+                    }
+                    // 自動アーカイブまでの時間を設定
+                    if let Some(minutes) = auto_archive_minutes {
+                        m.auto_archive_duration(minutes);
+                    }
+                    m
+                })
+                .await
+            {
+                Ok(thread) => return Ok(thread),
+                Err(why) => why,
+            };
+
+            // メッセージから作成したスレッドはメッセージと同じIDになるので、既に作成されていればそれを使う
+            if let Ok(Channel::Guild(thread)) = ChannelId(message.id.0).to_channel(ctx).await {
+                warn!(
+                    "スレッドの作成に失敗しましたが、既に作成されていたスレッド({})を使います: {:?}",
+                    thread.id, why
+                );
+                return Ok(thread);
+            }
+            if attempt >= THREAD_CREATE_MAX_ATTEMPTS {
+                return Err(why).context("スレッドの作成に失敗");
+            }
+            warn!(
+                "スレッドの作成に失敗 ({}/{}回目): {:?}",
+                attempt, THREAD_CREATE_MAX_ATTEMPTS, why
+            );
+            sleep(THREAD_CREATE_RETRY_INTERVAL).await;
+            attempt += 1;
+        }
+    }
+
+    /// VCのテキストチャットにスレッドへの案内を投稿する
+    /// 一時的な失敗に備えて何度か再試行し、それでも失敗した場合はログを出力だけしておく
+    async fn post_thread_link_to_vc(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+        thread_id: ChannelId,
+    ) {
+        for attempt in 1..=VC_TEXT_MAX_ATTEMPTS {
+            match vc_channel_id
+                .send_message(ctx, |m| {
+                    m.content(format!("VCチャット→ {}", thread_id.mention()));
+                    m
+                })
+                .await
+            {
+                Ok(_) => return,
+                Err(why) if attempt < VC_TEXT_MAX_ATTEMPTS => {
+                    warn!(
+                        "VCチャットの案内メッセージ作成に失敗、再試行します ({}/{}): {:?}",
+                        attempt, VC_TEXT_MAX_ATTEMPTS, why
+                    );
+                    sleep(VC_TEXT_RETRY_INTERVAL).await;
+                }
+                Err(why) => {
+                    error!("VCチャットの案内メッセージ作成に失敗: {:?}", why);
+                }
+            }
+        }
+    }
+
+    /// VC作成時に議題メッセージに付けるリアクションを取得する
+    fn vc_creation_reaction(&self) -> Option<ReactionType> {
+        let emoji = self.app_config.discord.vc_creation_reaction.as_deref()?;
+        ReactionType::try_from(emoji).ok()
+    }
+
+    /// VCのスレッドに退出メッセージを送る
+    async fn post_leave_message(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+        user_id: UserId,
+        member: Option<&Member>,
+    ) -> Result<(), BotError> {
+        // スレッドが作成されていない場合はエラー
+        let thread_id = self
+            .session_for_vc(vc_channel_id)
+            .await
+            .map(|s| s.thread_channel_id)
+            .ok_or(BotError::SessionNotFound)?;
+
+        // 退出メッセージ (メンバー情報がなければメンションで表示する)
+        let name = member
+            .map(display_name_or_mention)
+            .unwrap_or_else(|| user_id.mention().to_string());
+        thread_id
+            .send_message(ctx, |m| {
+                self.set_formatted_content(m, format!("{} さんが退出しました。", name), None)
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// スレッドの作成/投稿に失敗したことをVCのテキストチャットに知らせる
+    async fn notify_thread_failure(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+        why: &anyhow::Error,
+    ) {
+        self.count_api_error(why);
+        self.audit(
+            ctx,
+            AuditAction::Error {
+                details: format!(
+                    "{} のスレッドの作成/投稿に失敗: {:#}",
+                    vc_channel_id.mention(),
+                    why
+                ),
+            },
+        )
+        .await;
+        match vc_channel_id
+            .send_message(ctx, |m| {
+                m.content("⚠️VCチャットのスレッドを作成できませんでした\nしばらくしてから入り直してみてください");
+                m
+            })
+            .await
+        {
+            Ok(_) => {}
+            Err(why) => {
+                // VCのテキストチャットにも送れない場合はログを出力だけしておく
+                error!("スレッド作成失敗の通知に失敗: {:?}", why);
+            }
+        }
+    }
+
+    /// 設定されたボタン付きの参加メッセージを送信する
+    async fn send_welcome_message(
+        &self,
+        ctx: &Context,
+        thread_id: ChannelId,
+        content: String,
+        created_at: DateTime<Utc>,
+    ) -> Result<Message> {
+        let message = thread_id
+            .send_message(ctx, |m| {
+                self.set_formatted_content(m, content, Some(created_at));
+                // ボタンがない場合は空の行を作らない
+                let discord = &self.app_config.discord;
+                if discord.rename_button_placement.in_thread_welcome()
+                    && !discord.buttons.is_empty()
+                {
+                    m.components(|c| self.create_buttons(c));
+                }
+                m
+            })
+            .await?;
+        Ok(message)
+    }
+
+    /// 設定されたボタンを1行に並べる
+    fn create_buttons<'a>(&self, c: &'a mut CreateComponents) -> &'a mut CreateComponents {
+        c.create_action_row(|f| {
+            for button in &self.app_config.discord.buttons {
+                f.create_button(|b| {
+                    b.label(&button.label);
+                    b.style(button.style.into());
+                    b.custom_id(&button.custom_id);
+                    b
+                });
+            }
+            f
+        });
+        c
+    }
+
+    /// インタラクションが起きたチャンネルとメッセージから、対象のVCのスレッドを求める
+    /// 議題メッセージのボタンから操作された場合は、その議題メッセージから作成したスレッドを返す
+    async fn interaction_thread(
+        &self,
+        channel_id: ChannelId,
+        message_id: Option<MessageId>,
+    ) -> ChannelId {
+        let sessions = self.sessions.lock().await;
+        message_id
+            .and_then(|message_id| {
+                sessions
+                    .values()
+                    .filter_map(ThreadState::active)
+                    .find(|s| s.agenda_message.as_ref().map(|m| m.id) == Some(message_id))
+            })
+            .map(|s| s.thread_channel_id)
+            .unwrap_or(channel_id)
+    }
+
+    /// 設定された書式(プレーンテキスト/埋め込み)でメッセージの本文を設定する
+    /// 埋め込みの場合、VCの作成時刻が指定されていればフッターに表示する
+    fn set_formatted_content<'a, 'b>(
+        &self,
+        m: &'b mut CreateMessage<'a>,
+        content: String,
+        created_at: Option<DateTime<Utc>>,
+    ) -> &'b mut CreateMessage<'a> {
+        match self.app_config.discord.message_format {
+            MessageFormat::PlainText => m.content(content),
+            MessageFormat::Embed => m.embed(|e| {
+                e.description(content);
+                e.colour(EMBED_COLOUR);
+                if let Some(created_at) = created_at {
+                    e.footer(|f| f.text("VC作成"));
+                    e.timestamp(created_at);
+                }
+                e
+            }),
+        }
+    }
+
+    /// 議題メッセージが外部から削除された時に記録から外す
+    async fn forget_agenda_message(&self, channel_id: ChannelId, deleted_message_id: MessageId) {
+        for session in self
+            .sessions
+            .lock()
+            .await
+            .values_mut()
+            .filter_map(ThreadState::active_mut)
+        {
+            if session
+                .agenda_message
+                .as_ref()
+                .map(|m| m.channel_id == channel_id && m.id == deleted_message_id)
+                .unwrap_or(false)
+            {
+                session.agenda_message = None;
+            }
+        }
+    }
+
+    /// 参加メッセージが削除された時に再作成する
+    async fn recreate_welcome_message(
+        &self,
+        ctx: &Context,
+        thread_id: ChannelId,
+        deleted_message_id: MessageId,
+    ) -> Result<()> {
+        // 削除されたメッセージが参加メッセージでなければ何もしない
+        let welcome_message_id = self
+            .thread_to_welcome_message
+            .lock()
+            .await
+            .get(&thread_id)
+            .copied();
+        if welcome_message_id != Some(deleted_message_id) {
+            return Ok(());
+        }
+
+        // スレッド→セッションを取得
+        let session = match self.session_for_thread(&thread_id).await {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        let vc_channel_id = session.vc_channel_id;
+        // VCの作成時刻を計算
+        let created_at = Utc::now() - chrono::Duration::from_std(session.start_time.elapsed())?;
+        // VCを作成したユーザーを取得
+        let creator = self
+            .vc_session_creator
+            .lock()
+            .await
+            .get(&vc_channel_id)
+            .map(|user_id| format!("{} ", user_id.mention()))
+            .unwrap_or_default();
+        // チャンネル名を取得
+        let channel_name = vc_channel_id
+            .name(&ctx)
+            .await
+            .unwrap_or("不明なVC".to_string());
+
+        // 参加メッセージを再作成
+        let welcome_message = self
+            .send_welcome_message(
+                ctx,
+                thread_id,
+                format!(
+                    "{}`{}`へようこそ。\n興味を引くチャンネル名に変えてみんなを呼び込もう！\n（ボタンが再作成されました）",
+                    creator, &channel_name
+                ),
+                created_at,
+            )
+            .await
+            .context("参加メッセージの再作成に失敗")?;
+
+        // スレッドID->参加メッセージを登録
+        self.thread_to_welcome_message
+            .lock()
+            .await
+            .insert(thread_id, welcome_message.id);
+
+        Ok(())
+    }
+
+    /// VC名前変更時にスレッドをリネームする
+    async fn rename_thread(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+    ) -> Result<(), BotError> {
+        // マップからスレッドのチャンネルIDを取得 (スレッドが作成されていない場合はエラー)
+        let thread_id = self
+            .session_for_vc(vc_channel_id)
+            .await
+            .map(|s| s.thread_channel_id)
+            .ok_or(BotError::SessionNotFound)?;
+
+        // チャンネル名を取得
+        let channel_name = vc_channel_id
+            .name(&ctx)
+            .await
+            .unwrap_or("不明なVC".to_string());
+        // スレッド名に表示する人数を取得
+        let member_count = self
+            .thread_name_member_counts
+            .lock()
+            .await
+            .get(vc_channel_id)
+            .copied()
+            .unwrap_or(0);
+        // スレッドをリネーム
+        thread_id
+            .edit_thread(ctx, |t| {
+                t.name(self.thread_name(&channel_name, member_count));
+                t
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// スレッド名を作成する
+    /// 設定で有効な場合は、VCの名前の前に参加人数を付ける
+    fn thread_name(&self, vc_name: &str, member_count: usize) -> String {
+        if !self.app_config.discord.show_member_count_in_thread_name {
+            return vc_name.to_string();
+        }
+        let prefix = self
+            .app_config
+            .discord
+            .thread_name_member_count_format
+            .replace("{count}", &member_count.to_string());
+        format!("{}{}", prefix, vc_name)
+    }
+
+    /// スレッド名の参加人数を更新する
+    /// 人数が変わった場合のみ、スレッドの更新を予約する
+    async fn update_thread_name_member_count(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+    ) -> Result<(), BotError> {
+        // 設定で無効な場合は何もしない
+        if !self.app_config.discord.show_member_count_in_thread_name {
+            return Ok(());
+        }
+
+        // スレッドが作成されていない場合はエラー
+        let thread_id = self
+            .session_for_vc(vc_channel_id)
+            .await
+            .map(|s| s.thread_channel_id)
+            .ok_or(BotError::SessionNotFound)?;
+
+        // 人数が変わっていなければ何もしない
+        let member_count = self.vc_member_count(ctx, vc_channel_id).await?;
+        let previous = self
+            .thread_name_member_counts
+            .lock()
+            .await
+            .insert(*vc_channel_id, member_count);
+        if previous == Some(member_count) {
+            return Ok(());
+        }
+
+        // スレッドのリネームを予約
+        self.schedule_thread_update(thread_id).await;
+
+        Ok(())
+    }
+
+    /// スレッドの更新を予約する
+    /// 短時間に何度も更新するとレートリミットにかかるため、一定間隔でまとめて更新する
+    async fn schedule_thread_update(&self, thread_id: ChannelId) {
+        self.topic_update_pending.lock().await.insert(thread_id);
+    }
+
+    /// 予約されたスレッドの更新をまとめて行う
+    async fn flush_thread_updates(&self, ctx: &Context) {
+        let pending = std::mem::take(&mut *self.topic_update_pending.lock().await);
+        for thread_id in pending {
+            // スレッドに対応するVCを取得
+            let vc_channel_id = match self.session_for_thread(&thread_id).await {
+                Some(session) => session.vc_channel_id,
+                None => continue,
+            };
+
+            // VCスレッドチャンネルをリネーム
+            match self.rename_thread(ctx, &vc_channel_id).await {
+                // リネームまでの間にVCが終了した場合は何もしない
+                Ok(_) | Err(BotError::SessionNotFound) => {}
+                Err(why) => {
+                    error!("VCスレッドチャンネルのリネームに失敗: {:?}", why);
+                }
+            }
+        }
+    }
+
+    /// 管理中のセッションの数 (後始末中のものを含む)
+    async fn session_count(&self) -> usize {
+        self.sessions
+            .lock()
+            .await
+            .values()
+            .filter(|state| state.session().is_some())
+            .count()
+    }
+
+    /// VCの進行中のセッションを取得する
+    async fn session_for_vc(&self, vc_channel_id: &ChannelId) -> Option<VcSession> {
+        self.sessions
+            .lock()
+            .await
+            .get(vc_channel_id)
+            .and_then(ThreadState::active)
+            .cloned()
+    }
+
+    /// VCのセッションの議題メッセージを取得する
+    /// セッションがなければNone、セッションはあるが議題メッセージが存在しなければSome(None)を返す
+    async fn agenda_message_for_vc(&self, vc_channel_id: &ChannelId) -> Option<Option<Message>> {
+        self.sessions
+            .lock()
+            .await
+            .get(vc_channel_id)
+            .and_then(ThreadState::active)
+            .map(|s| s.agenda_message.clone())
+    }
+
+    /// スレッドの進行中のセッションを取得する
+    async fn session_for_thread(&self, thread_channel_id: &ChannelId) -> Option<VcSession> {
+        self.sessions
+            .lock()
+            .await
+            .values()
+            .filter_map(ThreadState::active)
+            .find(|s| s.thread_channel_id == *thread_channel_id)
+            .cloned()
+    }
+
+    /// セッション中に動かしているタスクを中断する
+    async fn abort_session_tasks(&self, vc_channel_id: &ChannelId) {
+        if let Some(tasks) = self.session_tasks.lock().await.remove(vc_channel_id) {
+            for task in tasks {
+                task.abort();
+            }
+        }
+    }
+
+    /// VCのセッションを後始末せずに記録から取り除く (紐づけの解除など)
+    async fn remove_session(&self, vc_channel_id: &ChannelId) -> Option<VcSession> {
+        self.abort_session_tasks(vc_channel_id).await;
+        let session = self
+            .sessions
+            .lock()
+            .await
+            .shift_remove(vc_channel_id)
+            .and_then(|state| state.session().cloned());
+        // 空きができたので、上限に達したVCには再び通知する
+        self.session_cap_warned_channels.lock().await.clear();
+        session
+    }
+
+    /// 進行中のセッションを後始末中にする
+    /// 進行中でない場合(既に他のイベントで後始末中など)はNoneを返し、二重に後始末しないようにする
+    async fn begin_finalizing(&self, vc_channel_id: &ChannelId) -> Option<VcSession> {
+        let session = {
+            let mut sessions = self.sessions.lock().await;
+            let state = sessions.get_mut(vc_channel_id)?;
+            let session = state.active()?.clone();
+            *state = ThreadState::Finalizing(session.clone());
+            session
+        };
+        self.abort_session_tasks(vc_channel_id).await;
+        Some(session)
+    }
+
+    /// 後始末が終わったセッションを記録から取り除く
+    async fn finish_finalizing(&self, vc_channel_id: &ChannelId) {
+        {
+            let mut sessions = self.sessions.lock().await;
+            if let Some(ThreadState::Finalizing(_)) = sessions.get(vc_channel_id) {
+                sessions.shift_remove(vc_channel_id);
+            }
+        }
+        // 空きができたので、上限に達したVCには再び通知する
+        self.session_cap_warned_channels.lock().await.clear();
+    }
+
+    /// VCのセッションを終了し、議題メッセージとスレッドを後始末する
+    /// 進行中のセッションがなければ(既に他のイベントで後始末中など)何もせずfalseを返す
+    async fn end_session(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+        reason: SessionEndReason,
+    ) -> bool {
+        // VCごとの記録を削除し、セッションを後始末中にする
+        self.clear_vc_records(vc_channel_id).await;
+        let session = match self.begin_finalizing(vc_channel_id).await {
+            Some(session) => session,
+            None => return false,
+        };
+        debug!(
+            "VC({})のセッションを終了します: {:?}",
+            vc_channel_id, reason
+        );
+
+        // 終了を通知
+        self.notify_session_end(&session);
+
+        // VC終了間際に投稿されたメッセージも数えられるよう、少し待ってから後始末する
+        if reason.waits_for_grace_period() {
+            sleep(Duration::from_secs(
+                self.app_config.discord.post_vc_grace_period_secs,
+            ))
+            .await;
+        }
+
+        // VCで誰も喋ってなかったら議題メッセージを削除
+        let should_delete = match self.finalize_agenda_message(ctx, &session).await {
+            Ok(del) => del,
+            Err(why) => {
+                error!("議題メッセージの後始末に失敗: {:?}", why);
+                self.count_api_error(&why);
+                false
+            }
+        };
+
+        // セッションを記録
+        self.record_session_end(&session, should_delete);
+
+        // スレッドを削除/アーカイブし、セッションを記録から取り除く
+        let result = self
+            .close_thread(ctx, &session.thread_channel_id, should_delete)
+            .await;
+        self.finish_finalizing(vc_channel_id).await;
+        if let Err(why) = result {
+            error!("VCスレッドチャンネルの後始末に失敗: {:?}", why);
+            self.count_api_error(&why);
+            self.audit(
+                ctx,
+                AuditAction::Error {
+                    details: format!(
+                        "{} の後始末に失敗: {:#}",
+                        session.thread_channel_id.mention(),
+                        why
+                    ),
+                },
+            )
+            .await;
+        }
+
+        true
+    }
+
+    /// 一定時間後にセッションの途中経過を投稿するタスクを開始する
+    async fn schedule_mid_session_summary(&self, ctx: &Context, vc_channel_id: ChannelId) {
+        let handler = self.clone();
+        let ctx = ctx.clone();
+        let task = tokio::spawn(async move {
+            sleep(MID_SESSION_SUMMARY_DELAY).await;
+            match handler.post_mid_session_summary(&ctx, &vc_channel_id).await {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("途中経過の投稿に失敗: {:?}", why);
+                }
+            }
+        });
+        self.add_session_task(vc_channel_id, task).await;
+    }
+
+    /// 作成直後に設定した低速モードを、一定時間後に解除するタスクを開始する
+    async fn schedule_slowmode_lift(
+        &self,
+        ctx: &Context,
+        vc_channel_id: ChannelId,
+        thread_id: ChannelId,
+    ) {
+        if self.app_config.discord.thread_slowmode_seconds == 0 {
+            return;
+        }
+        let delay = match self.app_config.discord.slowmode_lift_after_seconds {
+            Some(seconds) => Duration::from_secs(seconds),
+            None => return,
+        };
+        let ctx = ctx.clone();
+        let task = tokio::spawn(async move {
+            sleep(delay).await;
+            match thread_id.edit(&ctx, |c| c.rate_limit_per_user(0)).await {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("スレッドの低速モードの解除に失敗: {:?}", why);
+                }
+            }
+        });
+        self.add_session_task(vc_channel_id, task).await;
+    }
+
+    /// 一定時間スレッドで発言がなければ、声をかけるタスクを開始する
+    /// 一度声をかけたら、再び発言があるまでは声をかけない
+    async fn watch_inactivity(&self, ctx: &Context, vc_channel_id: ChannelId) {
+        let threshold = match self.app_config.discord.inactivity_warning_minutes {
+            Some(minutes) => Duration::from_secs(minutes * 60),
+            None => return,
+        };
+        let handler = self.clone();
+        let ctx = ctx.clone();
+        let task = tokio::spawn(async move {
+            // 声をかけた時点の最後の発言時刻
+            let mut warned_activity = None;
+            loop {
+                sleep(INACTIVITY_CHECK_INTERVAL).await;
+                // セッションが終了していれば終わる
+                let session = match handler.session_for_vc(&vc_channel_id).await {
+                    Some(session) => session,
+                    None => return,
+                };
+                // 最後の発言時刻 (発言がなければセッションの開始時刻)
+                let last_activity = handler
+                    .thread_last_human_message
+                    .lock()
+                    .await
+                    .get(&session.thread_channel_id)
+                    .copied()
+                    .unwrap_or(session.start_time);
+                if last_activity.elapsed() < threshold || warned_activity == Some(last_activity) {
+                    continue;
+                }
+                match session
+                    .thread_channel_id
+                    .send_message(&ctx, |m| {
+                        handler.set_formatted_content(
+                            m,
+                            "しばらく動きがないようですが、まだ通話中ですか？".to_string(),
+                            None,
+                        )
+                    })
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(why) => {
+                        error!("発言がないことの通知に失敗: {:?}", why);
+                    }
+                }
+                warned_activity = Some(last_activity);
+            }
+        });
+        self.add_session_task(vc_channel_id, task).await;
+    }
+
+    /// VCのスレッドの発言をライブ転送チャンネルに転送する
+    /// 連投でチャンネルが溢れないよう、ここでは転送を待つ発言として記録し、一定間隔でまとめて転送する
+    async fn forward_to_transcript(&self, ctx: &Context, message: &Message) {
+        if self.app_config.discord.live_transcript_channel.is_none() {
+            return;
+        }
+
+        // 本文がなければ添付ファイルのURLを転送する
+        let content = std::iter::once(message.content.as_str())
+            .chain(message.attachments.iter().map(|a| a.url.as_str()))
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if content.is_empty() {
+            return;
+        }
+
+        let thread_name = message
+            .channel_id
+            .name(ctx)
+            .await
+            .unwrap_or_else(|| "不明なスレッド".to_string());
+        let user_name = display_name(
+            message
+                .member
+                .as_ref()
+                .and_then(|member| member.nick.as_deref()),
+            &message.author,
+        );
+        let line = format!("[{}] {}: {}", thread_name, user_name, content)
+            .chars()
+            .take(MAX_MESSAGE_CHARS)
+            .collect::<String>();
+
+        // 連投で溢れないよう、定期的にまとめて転送する
+        self.transcript_queue
+            .lock()
+            .await
+            .entry(message.channel_id)
+            .or_default()
+            .push(line);
+    }
+
+    /// ライブ転送を待っている発言を、スレッドごとにまとめて転送する
+    async fn flush_transcript(&self, ctx: &Context) {
+        let transcript_channel = match self.app_config.discord.live_transcript_channel {
+            Some(channel) => channel,
+            None => return,
+        };
+        let queue = std::mem::take(&mut *self.transcript_queue.lock().await);
+        for (_, lines) in queue {
+            for content in batch_lines(&lines, MAX_MESSAGE_CHARS) {
+                match transcript_channel
+                    .send_message(ctx, |m| {
+                        m.content(content);
+                        // 転送先でメンションが飛ばないようにする
+                        m.allowed_mentions(|a| a.empty_parse());
+                        m
+                    })
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(why) => {
+                        error!("ライブ転送チャンネルへの送信に失敗: {:?}", why);
+                    }
+                }
+            }
+        }
+    }
+
+    /// セッションの終了時に中断するタスクを登録する
+    async fn add_session_task(&self, vc_channel_id: ChannelId, task: JoinHandle<()>) {
+        self.session_tasks
+            .lock()
+            .await
+            .entry(vc_channel_id)
+            .or_default()
+            .push(task);
+    }
+
+    /// セッションの途中経過(参加者、通話時間、発言数)をスレッドに投稿してピン留めする
+    async fn post_mid_session_summary(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+    ) -> Result<()> {
+        // 既にセッションが終了している場合は何もしない
+        let session = match self.session_for_vc(vc_channel_id).await {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+
+        // 参加者リストを作成 (参加者が多くてもフィールドの上限に収まるよう切り詰める)
+        let participants = truncate_field(
+            session
+                .participants
+                .iter()
+                .map(|user_id| user_id.mention().to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+
+        let message = session
+            .thread_channel_id
+            .send_message(ctx, |m| {
+                m.embed(|e| {
+                    e.title("途中経過");
+                    e.field("通話時間", session.start_time.elapsed().hhmmss(), true);
+                    e.field("メッセージ数", format!("{}件", session.message_count), true);
+                    e.field("参加者", participants, false);
+                    e.colour(EMBED_COLOUR);
+                    e
+                });
+                m.allowed_mentions(|m| m.empty_users());
+                m
+            })
+            .await
+            .context("途中経過の投稿に失敗")?;
+        message.pin(ctx).await.context("途中経過のピン留めに失敗")?;
+
+        // 後始末できるよう、途中経過のメッセージを記録する
+        if let Some(session) = self
+            .sessions
+            .lock()
+            .await
+            .get_mut(vc_channel_id)
+            .and_then(ThreadState::active_mut)
+        {
+            session.summary_message_id = Some(message.id);
+        }
+
+        Ok(())
+    }
+
+    /// セッションの終了をWebhookで通知する
+    fn notify_session_end(&self, session: &VcSession) {
+        if !self.app_config.webhook_on_end {
+            return;
+        }
+        self.notify_webhook(json!({
+            "event": "session_end",
+            "vc_id": session.vc_channel_id.to_string(),
+            "thread_id": session.thread_channel_id.to_string(),
+            "timestamp": Utc::now().to_rfc3339(),
+            "duration_secs": session.start_time.elapsed().as_secs(),
+            "participants": session
+                .participants
+                .iter()
+                .map(|user_id| user_id.to_string())
+                .collect::<Vec<_>>(),
+        }));
+    }
+
+    /// Webhookで通知する
+    /// イベントの処理を止めないよう、別タスクで送信する
+    fn notify_webhook(&self, payload: Value) {
+        let url = match &self.app_config.webhook_url {
+            Some(url) => url.clone(),
+            None => return,
+        };
+        tokio::spawn(async move {
+            match webhook::fire_webhook(&url, payload).await {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("Webhookの通知に失敗: {:?}", why);
+                }
+            }
+        });
+    }
+
+    /// 終了したセッションを設定された記録ファイルに追記する
+    fn record_session_end(&self, session: &VcSession, is_empty: bool) {
+        let path = match &self.app_config.telemetry_file {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        let end_time = Utc::now();
+        let start_time = end_time
+            - chrono::Duration::from_std(session.start_time.elapsed())
+                .unwrap_or_else(|_| chrono::Duration::zero());
+        let record = telemetry::SessionRecord::new(
+            session.vc_channel_id,
+            session.thread_channel_id,
+            start_time,
+            end_time,
+            session.participants.len(),
+            session.message_count,
+            is_empty && self.app_config.discord.on_empty_session == EmptySessionAction::Delete,
+        );
+        tokio::spawn(async move {
+            match telemetry::append_session(&path, &record).await {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("セッションの記録に失敗: {:?}", why);
+                }
+            }
+        });
+    }
+
+    /// VCごとの記録を削除する
+    async fn clear_vc_records(&self, vc_channel_id: &ChannelId) {
+        // 最初に参加したユーザーの記録を削除
+        self.vc_session_creator.lock().await.remove(vc_channel_id);
+        // VCのメンバーの記録を削除
+        self.vc_to_members.lock().await.remove(vc_channel_id);
+        // スレッド作成の保留を解除
+        self.vc_pending.lock().await.remove(vc_channel_id);
+        // 満員に近いことを警告した記録を削除
+        self.warned_channels.lock().await.remove(vc_channel_id);
+        // 上限に達したことを通知した記録を削除
+        self.session_cap_warned_channels
+            .lock()
+            .await
+            .remove(vc_channel_id);
+        // 名前変更の履歴と回数を削除
+        self.vc_rename_history.lock().await.remove(vc_channel_id);
+        self.vc_rename_count.lock().await.remove(vc_channel_id);
+        // スレッド名に表示した人数の記録を削除
+        self.thread_name_member_counts
+            .lock()
+            .await
+            .remove(vc_channel_id);
+    }
+
+    /// Botがいなくなったサーバーのセッションと記録を全て削除する (スレッドには触れない)
+    /// `channel_ids` にはキャッシュに残っていたサーバーのチャンネルを渡し、セッションのないVCの記録も削除する
+    async fn forget_guild(&self, guild_id: GuildId, channel_ids: Vec<ChannelId>) {
+        let mut vc_channel_ids = self
+            .sessions
+            .lock()
+            .await
+            .values()
+            .filter_map(ThreadState::session)
+            .filter(|s| s.guild_id == guild_id)
+            .map(|s| s.vc_channel_id)
+            .collect::<HashSet<_>>();
+        vc_channel_ids.extend(channel_ids);
+
+        let mut session_count = 0;
+        for vc_channel_id in &vc_channel_ids {
+            if let Some(session) = self.remove_session(vc_channel_id).await {
+                session_count += 1;
+                self.thread_last_human_message
+                    .lock()
+                    .await
+                    .remove(&session.thread_channel_id);
+                self.thread_to_welcome_message
+                    .lock()
+                    .await
+                    .shift_remove(&session.thread_channel_id);
+                self.transcript_queue
+                    .lock()
+                    .await
+                    .shift_remove(&session.thread_channel_id);
+            }
+            self.clear_vc_records(vc_channel_id).await;
+            self.vc_pinned_messages.lock().await.remove(vc_channel_id);
+            self.vc_custom_greetings.lock().await.remove(vc_channel_id);
+            self.vc_last_thread_created
+                .lock()
+                .await
+                .remove(vc_channel_id);
+        }
+        self.event_sessions
+            .lock()
+            .await
+            .retain(|_, vc_channel_id| !vc_channel_ids.contains(vc_channel_id));
+
+        info!(
+            "サーバー({})からBotがいなくなったため、{}件のセッションの記録を削除しました",
+            guild_id, session_count
+        );
+    }
+
+    /// VC終了時にスレッドを設定に応じて削除/アーカイブする
+    async fn close_thread(
+        &self,
+        ctx: &Context,
+        thread_channel_id: &ChannelId,
+        is_empty: bool,
+    ) -> Result<()> {
+        // 2件以上のメッセージがなければ会話がなかったものとして扱う
+        if is_empty {
+            match self.app_config.discord.on_empty_session {
+                EmptySessionAction::Delete => {
+                    // VCスレッドチャンネルを削除
+                    thread_channel_id
+                        .delete(&ctx)
+                        .await
+                        .context("VCスレッドチャンネルの削除に失敗")?;
+                    self.audit(
+                        ctx,
+                        AuditAction::ThreadDeleted {
+                            thread: *thread_channel_id,
+                        },
+                    )
+                    .await;
+                }
+                EmptySessionAction::Archive => {
+                    // VCスレッドチャンネルをアーカイブ
+                    archive_thread(ctx, thread_channel_id, false).await?;
+                    self.audit(
+                        ctx,
+                        AuditAction::ThreadArchived {
+                            thread: *thread_channel_id,
+                        },
+                    )
+                    .await;
+                }
+                EmptySessionAction::Nothing => {}
+            }
+            self.sessions_ended_without_messages
+                .fetch_add(1, Ordering::Relaxed);
+        } else {
+            match self.app_config.discord.on_active_session {
+                ActiveSessionAction::Archive => {
+                    // VCスレッドチャンネルをアーカイブ
+                    archive_thread(ctx, thread_channel_id, false).await?;
+                    self.audit(
+                        ctx,
+                        AuditAction::ThreadArchived {
+                            thread: *thread_channel_id,
+                        },
+                    )
+                    .await;
+                }
+                ActiveSessionAction::Lock => {
+                    // VCスレッドチャンネルをアーカイブしてロック
+                    archive_thread(ctx, thread_channel_id, true).await?;
+                    self.audit(
+                        ctx,
+                        AuditAction::ThreadArchived {
+                            thread: *thread_channel_id,
+                        },
+                    )
+                    .await;
+                }
+                ActiveSessionAction::Nothing => {}
+            }
+            self.sessions_ended_with_messages
+                .fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+
+    /// VCの参加人数を取得する
+    async fn vc_member_count(&self, ctx: &Context, vc_channel_id: &ChannelId) -> Result<usize> {
+        Ok(self.vc_members(ctx, vc_channel_id).await?.len())
+    }
+
+    /// VCの参加者を取得する
+    async fn vc_members(&self, ctx: &Context, vc_channel_id: &ChannelId) -> Result<Vec<Member>> {
+        let vc_channel = get_channel(ctx, *vc_channel_id)
+            .await
+            .context("チャンネルの取得に失敗")?
+            .guild()
+            .ok_or(anyhow::anyhow!("無効なVCチャンネルの種類"))?;
+        let members = vc_channel
+            .members(&ctx.cache)
+            .await
+            .context("VCのメンバー取得に失敗")?;
+        Ok(members)
+    }
+
+    /// VCの参加者をスレッドのメンバーに追加する
+    /// 追加に失敗したユーザーがいても、他のユーザーの追加は続ける
+    async fn add_vc_members_to_thread(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+        thread_id: ChannelId,
+    ) -> Result<()> {
+        let members = self.vc_members(ctx, vc_channel_id).await?;
+        for member in members.iter().filter(|m| !self.is_ignored_member(m)) {
+            match thread_id.add_thread_member(ctx, member.user.id).await {
+                Ok(_) => {}
+                Err(why) => {
+                    warn!(
+                        "スレッドへのメンバーの追加に失敗: ユーザー={}, {:?}",
+                        member.user.id, why
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// VCの参加人数が人数上限の設定された割合に達したら、スレッドに警告を投稿する
+    /// 一度警告したVCは、人数が割合を下回るまで再び警告しない
+    async fn check_user_limit(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+    ) -> Result<(), BotError> {
+        let threshold = self.app_config.discord.user_limit_warning_threshold;
+        if threshold <= 0.0 {
+            return Ok(());
+        }
+
+        // VCのスレッドを取得
+        let thread_channel_id = self
+            .session_for_vc(vc_channel_id)
+            .await
+            .ok_or(BotError::SessionNotFound)?
+            .thread_channel_id;
+
+        // 人数上限のないVCは対象外
+        let vc_channel = get_channel(ctx, *vc_channel_id)
+            .await?
+            .guild()
+            .ok_or(anyhow::anyhow!("無効なVCチャンネルの種類"))?;
+        let user_limit = match vc_channel.user_limit {
+            Some(user_limit) if user_limit > 0 => user_limit,
+            _ => return Ok(()),
+        };
+
+        // 割合に達したかを判定
+        let member_count = self.vc_member_count(ctx, vc_channel_id).await?;
+        if (member_count as f32) < user_limit as f32 * threshold {
+            self.warned_channels.lock().await.remove(vc_channel_id);
+            return Ok(());
+        }
+        if !self.warned_channels.lock().await.insert(*vc_channel_id) {
+            return Ok(());
+        }
+
+        // スレッドに警告を投稿
+        thread_channel_id
+            .send_message(ctx, |m| {
+                self.set_formatted_content(
+                    m,
+                    format!(
+                        "⚠️VCがもうすぐ満員です ({}/{}人)\n誘いたい人がいれば今のうちに声をかけましょう",
+                        member_count, user_limit
+                    ),
+                    None,
+                )
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// 議題メッセージの参加人数を更新する
+    async fn update_agenda_member_count(
+        &self,
+        ctx: &Context,
+        vc_channel_id: &ChannelId,
+    ) -> Result<(), BotError> {
+        // マップから議題メッセージを取得
+        let mut message = match self.agenda_message_for_vc(vc_channel_id).await {
+            Some(Some(message)) => message,
+            // 議題メッセージが削除済みの場合は更新しない
+            Some(None) => return Ok(()),
+            // セッションが管理されていない場合
+            None => return Err(BotError::SessionNotFound),
+        };
+
+        // 人数が変わっていなければ何もしない
+        let member_count = self.vc_member_count(ctx, vc_channel_id).await?;
+        let content = with_member_count(&message.content, member_count);
+        if content == message.content {
+            return Ok(());
+        }
+
+        // 議題メッセージを編集
+        message
+            .edit(ctx, |m| {
+                m.content(content);
+                m.allowed_mentions(|m| m.empty_users());
+                m
+            })
+            .await?;
+        if let Some(session) = self
+            .sessions
+            .lock()
+            .await
+            .get_mut(vc_channel_id)
+            .and_then(ThreadState::active_mut)
+        {
+            session.agenda_message = Some(message);
+        }
+
+        Ok(())
+    }
+
+    /// VCを取得
+    async fn get_vc(
+        &self,
+        ctx: &Context,
+        channel_id: &ChannelId,
+    ) -> Result<GuildChannel, BotError> {
+        // マップからVCのチャンネルIDを取得
+        let vc_channel_id = self
+            .session_for_thread(channel_id)
+            .await
+            .map(|s| s.vc_channel_id)
+            .ok_or(BotError::SessionNotFound)?;
+        let vc_channel = get_channel(ctx, vc_channel_id).await?;
+        let vc_channel = vc_channel
             .guild()
             .ok_or(anyhow::anyhow!("無効なVCチャンネルの種類"))?;
         Ok(vc_channel)
     }
 
-    /// VC名前変更時にスレッドをリネームする
+    /// このセッションで名前を変更できる回数の上限に達したか判定する
+    async fn rename_limit_reached(&self, vc_channel_id: &ChannelId) -> bool {
+        let max_renames = match self.bot_config.read().await.max_renames_per_session {
+            Some(max_renames) => max_renames,
+            None => return false,
+        };
+        let count = self
+            .vc_rename_count
+            .lock()
+            .await
+            .get(vc_channel_id)
+            .copied()
+            .unwrap_or(0);
+        count >= max_renames
+    }
+
+    /// VCの名前を変更できるか判定する
+    /// VCのオーナー(チャンネルの管理権限を持つユーザー)か、設定で許可されたロールを持つメンバーのみ変更できる
+    fn can_rename_vc(
+        &self,
+        ctx: &Context,
+        vc_channel: &GuildChannel,
+        user_id: UserId,
+        member: Option<&Member>,
+    ) -> bool {
+        // VCのオーナーか
+        let is_owner = vc_channel
+            .permissions_for_user(ctx, user_id)
+            .map(|vc_permission| vc_permission.manage_channels())
+            .unwrap_or(false);
+        if is_owner {
+            return true;
+        }
+
+        // 名前の変更を許可されたロールを持っているか
+        let allowed_roles = &self.app_config.discord.rename_allowed_roles;
+        member
+            .map(|m| m.roles.iter().any(|r| allowed_roles.contains(r)))
+            .unwrap_or(false)
+    }
+
+    /// 参加メッセージのボタンが押された時、権限をチェックしてボタンのIDごとの処理に振り分ける
     async fn button_pressed(
         &self,
         ctx: &Context,
         interaction: &MessageComponentInteraction,
     ) -> Result<()> {
+        // 設定にないボタン (設定を変える前のメッセージなど) は無視
+        let button = match self
+            .app_config
+            .discord
+            .buttons
+            .iter()
+            .find(|b| b.custom_id == interaction.data.custom_id)
+        {
+            Some(button) => button,
+            None => {
+                debug!(
+                    "設定にないボタンが押されました: {}",
+                    interaction.data.custom_id
+                );
+                return Ok(());
+            }
+        };
+
         // VCチャンネルを取得
-        let vc_channel = match self.get_vc(ctx, &interaction.channel_id).await {
+        // 参加メッセージ/議題メッセージのどちらのボタンでも、対応するスレッドからVCを求める
+        let thread_channel_id = self
+            .interaction_thread(interaction.channel_id, Some(interaction.message.id))
+            .await;
+        let vc_channel = match self.get_vc(ctx, &thread_channel_id).await {
             Ok(vc_channel) => vc_channel,
-            Err(_) => {
+            Err(BotError::SessionNotFound) => {
                 return {
                     interaction
                         .create_interaction_response(&ctx, |r| {
                             r.kind(InteractionResponseType::ChannelMessageWithSource)
                                 .interaction_response_data(|d| {
                                     d.content("❌そのVCは既に解散しています");
-                                    d.ephemeral(true);
+                                    d.ephemeral(self.app_config.discord.ephemeral_errors);
                                     d
                                 });
                             r
@@ -286,19 +2513,20 @@ impl Handler {
                     Ok(())
                 }
             }
+            Err(why) => return Err(why.into()),
         };
 
-        // VCの権限をチェック
-        match vc_channel.permissions_for_user(&ctx, interaction.user.id) {
-            Ok(vc_permission) if vc_permission.manage_channels() => {}
-            _ => {
+        // ボタンに必要な権限をチェック
+        match self.can_press_button(ctx, button, &vc_channel, interaction) {
+            true => {}
+            false => {
                 return {
                     interaction
                         .create_interaction_response(&ctx, |r| {
                             r.kind(InteractionResponseType::ChannelMessageWithSource)
                                 .interaction_response_data(|d| {
                                     d.content("❌VCのオーナーのみが名前を変更できます");
-                                    d.ephemeral(true);
+                                    d.ephemeral(self.app_config.discord.ephemeral_errors);
                                     d
                                 });
                             r
@@ -311,6 +2539,138 @@ impl Handler {
             }
         };
 
+        match button.custom_id.as_str() {
+            // このセッションで名前を変更できる回数の上限に達していればダイアログを開かない
+            RENAME_BUTTON_ID if self.rename_limit_reached(&vc_channel.id).await => {
+                interaction
+                    .create_interaction_response(&ctx, |r| {
+                        r.kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|d| {
+                                d.content(RENAME_LIMIT_MESSAGE);
+                                d.ephemeral(true);
+                                d
+                            });
+                        r
+                    })
+                    .await
+                    .context("エラー内容の応答に失敗")?;
+                Ok(())
+            }
+            RENAME_BUTTON_ID => self.open_rename_modal(ctx, interaction).await,
+            custom_id => Err(anyhow::anyhow!(
+                "処理が実装されていないボタン: {}",
+                custom_id
+            )),
+        }
+    }
+
+    /// 再開ボタンが押された時、アーカイブされたスレッドを再開する
+    /// VCカテゴリのチャンネル管理権限を持つユーザーのみ再開できる
+    async fn resume_button_pressed(
+        &self,
+        ctx: &Context,
+        interaction: &MessageComponentInteraction,
+    ) -> Result<()> {
+        // VCカテゴリの権限をチェック
+        let vc_category = match interaction.guild_id {
+            Some(guild_id) => self.app_config.discord.vc_category_for(guild_id),
+            None => self.app_config.discord.vc_category,
+        };
+        let can_manage = ctx
+            .cache
+            .guild_channel(vc_category)
+            .and_then(|category| category.permissions_for_user(ctx, interaction.user.id).ok())
+            .map(|permission| permission.manage_channels())
+            .unwrap_or(false);
+        if !can_manage {
+            interaction
+                .create_interaction_response(&ctx, |r| {
+                    r.kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|d| {
+                            d.content("❌VCカテゴリのチャンネル管理権限が必要です");
+                            d.ephemeral(self.app_config.discord.ephemeral_errors);
+                            d
+                        });
+                    r
+                })
+                .await
+                .context("エラー内容の応答に失敗")?;
+            return Ok(());
+        }
+
+        // スレッドのアーカイブを解除
+        let channel_is_thread = interaction
+            .channel_id
+            .to_channel(ctx)
+            .await
+            .context("ボタンが押されたチャンネルの取得に失敗")?
+            .guild()
+            .map(|channel| {
+                matches!(
+                    channel.kind,
+                    ChannelType::PublicThread | ChannelType::PrivateThread
+                )
+            })
+            .unwrap_or(false);
+        let thread_id = resume_target_thread(
+            interaction.channel_id,
+            channel_is_thread,
+            interaction.message.id,
+        );
+        thread_id
+            .edit_thread(ctx, |t| {
+                t.archived(false);
+                t
+            })
+            .await
+            .context("スレッドの再開に失敗")?;
+
+        // ボタンを外し、再開したことを書き添える
+        interaction
+            .create_interaction_response(&ctx, |r| {
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|d| {
+                        d.content(format!(
+                            "▶️{} がスレッドを再開しました",
+                            interaction.user.mention()
+                        ));
+                        d.components(|c| c);
+                        d.allowed_mentions(|m| m.empty_users());
+                        d
+                    });
+                r
+            })
+            .await
+            .context("終了メッセージの更新に失敗")?;
+
+        Ok(())
+    }
+
+    /// ボタンを押す権限があるか判定する
+    fn can_press_button(
+        &self,
+        ctx: &Context,
+        button: &ButtonConfig,
+        vc_channel: &GuildChannel,
+        interaction: &MessageComponentInteraction,
+    ) -> bool {
+        match button.required_permission {
+            ButtonPermission::VcOwner => self.can_rename_vc(
+                ctx,
+                vc_channel,
+                interaction.user.id,
+                interaction.member.as_ref(),
+            ),
+            ButtonPermission::Everyone => true,
+        }
+    }
+
+    /// 名前変更ボタンが押された時、名前を入力するダイアログを開く
+    async fn open_rename_modal(
+        &self,
+        ctx: &Context,
+        interaction: &MessageComponentInteraction,
+    ) -> Result<()> {
         // モーダルダイアログを開く
         interaction
             .create_interaction_response(&ctx, |r| {
@@ -322,13 +2682,29 @@ impl Handler {
                             c.create_action_row(|f| {
                                 f.create_input_text(|t| {
                                     t.custom_id("rename_text");
-                                    t.label("VCのテーマは？");
-                                    t.placeholder("フォートナイト, しりとり, カラオケ,...");
+                                    t.label(&self.app_config.discord.rename_modal_label);
+                                    t.placeholder(
+                                        &self.app_config.discord.rename_modal_placeholder,
+                                    );
                                     t.style(InputTextStyle::Short);
                                     t
                                 });
                                 f
                             });
+                            // 設定で有効な場合は、話題を入力する欄も追加する
+                            if self.app_config.discord.show_topic_input {
+                                c.create_action_row(|f| {
+                                    f.create_input_text(|t| {
+                                        t.custom_id("topic_text");
+                                        t.label("話題 (任意)");
+                                        t.placeholder("今日話したいことなど");
+                                        t.style(InputTextStyle::Paragraph);
+                                        t.required(false);
+                                        t
+                                    });
+                                    f
+                                });
+                            }
                             c
                         });
                         d
@@ -336,155 +2712,335 @@ impl Handler {
                 r
             })
             .await
-            .context("ダイアログの作成に失敗")?;
-
+            .context("ダイアログの作成に失敗")?;
+
+        Ok(())
+    }
+
+    /// 話題をスレッドに投稿してピン留めする
+    async fn post_topic(
+        &self,
+        ctx: &Context,
+        thread_channel_id: &ChannelId,
+        topic: &str,
+    ) -> Result<()> {
+        let message = thread_channel_id
+            .send_message(ctx, |m| {
+                self.set_formatted_content(m, format!("📌話題: {}", topic), None)
+            })
+            .await
+            .context("話題の投稿に失敗")?;
+        message.pin(ctx).await.context("話題のピン留めに失敗")?;
         Ok(())
     }
 
     /// VC名前変更時にスレッドをリネームする
     async fn rename_vc(&self, ctx: &Context, interaction: &ModalSubmitInteraction) -> Result<()> {
-        // VCチャンネルを取得
-        let mut vc_channel = match self.get_vc(ctx, &interaction.channel_id).await {
-            Ok(vc_channel) => vc_channel,
-            Err(_) => {
-                return {
-                    interaction
-                        .create_interaction_response(&ctx, |r| {
-                            r.kind(InteractionResponseType::ChannelMessageWithSource)
-                                .interaction_response_data(|d| {
-                                    d.content("❌そのVCは既に解散しています");
-                                    d.ephemeral(true);
-                                    d
-                                });
-                            r
-                        })
-                        .await
-                        .context("エラー内容の応答に失敗")?;
+        // VCの名前を取得
+        let name = modal_input_value(interaction, "rename_text")
+            .ok_or(anyhow::anyhow!("コンポーネントが見つかりません"))?;
+        // 話題を取得 (入力欄がないか、空の場合はNone)
+        let topic = modal_input_value(interaction, "topic_text").filter(|t| !t.trim().is_empty());
 
-                    Ok(())
+        // VCの名前を変更 (議題メッセージのボタンから開いたダイアログの場合は、そのスレッドのVCを変更する)
+        let thread_channel_id = self
+            .interaction_thread(
+                interaction.channel_id,
+                interaction.message.as_ref().map(|m| m.id),
+            )
+            .await;
+        let result = self
+            .change_vc_name(
+                ctx,
+                &thread_channel_id,
+                interaction.user.id,
+                interaction.member.as_ref(),
+                name,
+            )
+            .await?;
+
+        // 名前を変更できた場合は、話題をスレッドに投稿してピン留めする
+        // (スレッドにはチャンネルのトピックがないため、ピン留めしたメッセージをトピック代わりにする)
+        if let (RenameResult::Renamed, Some(topic)) = (&result, topic) {
+            match self.post_topic(ctx, &thread_channel_id, &topic).await {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("話題の投稿に失敗: {:?}", why);
                 }
             }
-        };
+        }
 
-        // VCの権限をチェック
-        match vc_channel
-            .permissions_for_user(&ctx, interaction.user.id)
-            .context("VCチャンネルのパーミッション取得に失敗")?
-        {
-            vc_permission if vc_permission.manage_channels() => {}
-            _ => {
-                return {
-                    interaction
-                        .create_interaction_response(&ctx, |r| {
-                            r.kind(InteractionResponseType::ChannelMessageWithSource)
+        // 返答
+        match result {
+            RenameResult::Renamed => {
+                // 議題メッセージのボタンから開いた場合は書き換えられないので、新しく投稿する
+                let response = match self.app_config.discord.rename_success_response {
+                    RenameSuccessResponse::UpdateOriginal
+                        if interaction.message.is_none()
+                            || interaction.channel_id != thread_channel_id =>
+                    {
+                        RenameSuccessResponse::NewMessage
+                    }
+                    response => response,
+                };
+                interaction
+                    .create_interaction_response(&ctx, |r| {
+                        let content =
+                            format!("✅{} が名前を変更しました", interaction.user.mention());
+                        match response {
+                            // ボタンを押したメッセージを書き換え、ボタンを外す
+                            RenameSuccessResponse::UpdateOriginal => r
+                                .kind(InteractionResponseType::UpdateMessage)
                                 .interaction_response_data(|d| {
-                                    d.content("❌VCのオーナーのみが名前を変更できます");
-                                    d.ephemeral(true);
+                                    d.content(content);
+                                    d.components(|c| c);
+                                    d.allowed_mentions(|m| m.empty_users());
                                     d
-                                });
-                            r
-                        })
-                        .await
-                        .context("エラー内容の応答に失敗")?;
+                                }),
+                            RenameSuccessResponse::NewMessage
+                            | RenameSuccessResponse::Ephemeral => r
+                                .kind(InteractionResponseType::ChannelMessageWithSource)
+                                .interaction_response_data(|d| {
+                                    d.content(content);
+                                    d.allowed_mentions(|m| m.empty_users());
+                                    d.ephemeral(
+                                        response == RenameSuccessResponse::Ephemeral
+                                            || self.app_config.discord.ephemeral_success,
+                                    );
+                                    d
+                                }),
+                        }
+                    })
+                    .await
+                    .context("結果の応答に失敗")?;
+            }
+            RenameResult::Rejected(reason) => {
+                interaction
+                    .create_interaction_response(&ctx, |r| {
+                        r.kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|d| {
+                                d.content(reason);
+                                d.ephemeral(self.app_config.discord.ephemeral_errors);
+                                d
+                            });
+                        r
+                    })
+                    .await
+                    .context("エラー内容の応答に失敗")?;
+            }
+        }
 
-                    Ok(())
-                }
+        Ok(())
+    }
+
+    /// スレッドに対応するVCの名前を変更する
+    /// 権限がない場合や名前が不正な場合は、利用者に表示する理由を返す
+    async fn change_vc_name(
+        &self,
+        ctx: &Context,
+        thread_channel_id: &ChannelId,
+        user_id: UserId,
+        member: Option<&Member>,
+        name: String,
+    ) -> Result<RenameResult> {
+        // VCチャンネルを取得
+        let mut vc_channel = match self.get_vc(ctx, thread_channel_id).await {
+            Ok(vc_channel) => vc_channel,
+            Err(BotError::SessionNotFound) => {
+                return Ok(RenameResult::Rejected(
+                    "❌そのVCは既に解散しています".to_string(),
+                ))
             }
+            Err(why) => return Err(why.into()),
         };
 
-        // VCの名前を取得
-        let name = interaction
-            .data
-            .components
-            .iter()
-            .flat_map(|c| c.components.iter())
-            .find_map(|c| match c {
-                ActionRowComponent::InputText(t) if t.custom_id == "rename_text" => {
-                    Some(t.value.clone())
-                }
-                _ => None,
-            })
-            .ok_or(anyhow::anyhow!("コンポーネントが見つかりません"))?;
+        // VCの権限をチェック
+        if !self.can_rename_vc(ctx, &vc_channel, user_id, member) {
+            return Ok(RenameResult::Rejected(
+                "❌VCのオーナーのみが名前を変更できます".to_string(),
+            ));
+        }
+
+        // VCの名前に使えない文字列が含まれていないかチェック
+        if let Some(reason) = validate_vc_name(&name) {
+            return Ok(RenameResult::Rejected(format!("❌{}", reason)));
+        }
+        if !self.app_config.discord.vc_name_char_filter.allows(&name) {
+            return Ok(RenameResult::Rejected(
+                "❌VCの名前に使えない文字が含まれています".to_string(),
+            ));
+        }
+
+        // このセッションで名前を変更できる回数の上限をチェック
+        if self.rename_limit_reached(&vc_channel.id).await {
+            return Ok(RenameResult::Rejected(RENAME_LIMIT_MESSAGE.to_string()));
+        }
 
         // VCの名前を変更
+        let old_name = vc_channel.name.clone();
         let future = vc_channel.edit(&ctx, |e| {
-            e.name(name);
+            e.name(&name);
             e
         });
         // レートリミットがかかると遅いので、2秒でタイムアウト
         let result = match timeout(Duration::from_secs(2), future).await {
             Ok(result) => result,
             Err(_) => {
-                return {
-                    interaction
-                        .create_interaction_response(&ctx, |r| {
-                            r.kind(InteractionResponseType::ChannelMessageWithSource)
-                                .interaction_response_data(|d| {
-                                    d.content("❌VCの名前の変更に失敗しました\n```\n短時間に名前変更をしすぎてDiscord APIのレート上限に引っかかった可能性があります\n10分ほど待って再度お試しください```");
-                                    d.ephemeral(true);
-                                    d
-                                });
-                            r
-                        })
-                        .await
-                        .context("エラー内容の応答に失敗")?;
-
-                    Ok(())
-                }
+                return Ok(RenameResult::Rejected(
+                    "❌VCの名前の変更に失敗しました\n```\n短時間に名前変更をしすぎてDiscord APIのレート上限に引っかかった可能性があります\n10分ほど待って再度お試しください```".to_string(),
+                ))
             }
         };
         // 権限などで失敗した場合はエラーを返す
         result.context("VCの名前変更に失敗")?;
-
-        // 返答
-        interaction
-            .create_interaction_response(&ctx, |r| {
-                r.kind(InteractionResponseType::ChannelMessageWithSource)
-                    .interaction_response_data(|d| {
-                        d.content(format!(
-                            "✅{} が名前を変更しました",
-                            interaction.user.mention()
-                        ));
-                        d.allowed_mentions(|m| m.empty_users());
-                        d
-                    });
-                r
-            })
+        self.renames_performed.fetch_add(1, Ordering::Relaxed);
+        *self
+            .vc_rename_count
+            .lock()
             .await
-            .context("結果の応答に失敗")?;
+            .entry(vc_channel.id)
+            .or_default() += 1;
+        self.audit(
+            ctx,
+            AuditAction::VcRenamed {
+                old: old_name.clone(),
+                new: name.clone(),
+                by: user_id,
+            },
+        )
+        .await;
 
-        Ok(())
+        // 名前変更の履歴を記録 (設定された件数を超えたら古いものから削除)
+        {
+            let mut rename_history = self.vc_rename_history.lock().await;
+            let history = rename_history.entry(vc_channel.id).or_default();
+            history.push_back((old_name, user_id, Utc::now()));
+            while history.len() > self.app_config.discord.rename_history_limit {
+                history.pop_front();
+            }
+        }
+
+        Ok(RenameResult::Renamed)
     }
 
-    /// スレッドの議題メッセージを後始末する
-    async fn finalize_agenda_message(
+    /// 期間をサーバーのタイムゾーンで表示用の文字列にする
+    /// タイムゾーンの指定がなければUTCで表示し、利用者が時差を補えるよう `(UTC)` を付ける
+    fn format_local_period(
         &self,
-        ctx: &Context,
-        thread_channel_id: &ChannelId,
-    ) -> Result<bool> {
-        // 最近5件のメッセージを取得
-        let messages = thread_channel_id
-            .messages(&ctx, |f| {
-                f.limit(5);
-                f
-            })
+        guild_id: GuildId,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> String {
+        const FORMAT: &str = "%Y/%m/%d %H:%M";
+        match self.app_config.discord.timezone_for(guild_id) {
+            Some(tz) => format!(
+                "{} 〜 {}",
+                start.with_timezone(&tz).format(FORMAT),
+                end.with_timezone(&tz).format(FORMAT)
+            ),
+            None => format!("{} 〜 {} (UTC)", start.format(FORMAT), end.format(FORMAT)),
+        }
+    }
+
+    /// スレッドの議題メッセージを後始末する
+    async fn finalize_agenda_message(&self, ctx: &Context, session: &VcSession) -> Result<bool> {
+        let thread_channel_id = &session.thread_channel_id;
+
+        // 途中経過のメッセージは終了メッセージと重複するので削除する
+        if let Some(summary_message_id) = session.summary_message_id {
+            match thread_channel_id
+                .delete_message(ctx, summary_message_id)
+                .await
+            {
+                Ok(_) => {}
+                Err(why) => {
+                    warn!("途中経過のメッセージの削除に失敗: {:?}", why);
+                }
+            }
+        }
+
+        // `/pinmessage` でピン留めしたメッセージのピン留めを外す
+        let pinned_messages = self
+            .vc_pinned_messages
+            .lock()
+            .await
+            .remove(&session.vc_channel_id)
+            .unwrap_or_default();
+        for message_id in pinned_messages {
+            match thread_channel_id.unpin(ctx, message_id).await {
+                Ok(_) => {}
+                Err(why) => {
+                    warn!("メッセージのピン留めの解除に失敗: {:?}", why);
+                }
+            }
+        }
+
+        // 議題メッセージを取得 (削除済みの場合はNone。後で終了メッセージを新たに投稿する)
+        let mut message = session.agenda_message.clone();
+        if message.is_none() {
+            warn!(
+                "議題メッセージが存在しないため、終了メッセージを新たに投稿します: スレッド={}",
+                thread_channel_id
+            );
+        }
+
+        // Botを取得 (キャッシュにまだなければ準備完了時に記録したIDを使う)
+        let bot = match ctx.cache.current_user_id() {
+            bot if bot.0 != 0 => bot,
+            _ => *self
+                .bot_user_id
+                .get()
+                .context("自身のBotユーザーの取得に失敗")?,
+        };
+
+        // 最後に人間が発言した時刻を取得
+        let last_human_message = self
+            .thread_last_human_message
+            .lock()
             .await
-            .context("メッセージ取得に失敗")?;
+            .remove(thread_channel_id);
 
-        // チャンネルID->議題メッセージを取得
-        let mut message_map = self.thread_to_agenda_message.lock().await;
-        let message = match message_map.get_mut(&thread_channel_id) {
-            Some(message) => message,
-            None => return Ok(false),
+        // スレッドの後始末の方針を決める
+        let decision = match last_human_message {
+            // 最近人間が発言していれば、メッセージを取得せずに議題メッセージを書き換える
+            Some(last_message) if last_message.elapsed() < RECENT_HUMAN_MESSAGE_WINDOW => {
+                FinalizationDecision::Edit
+            }
+            // 発言の記録がないか古い場合は、メッセージを遡って取得して判断する
+            // 早い時間に発言があった後にBotの投稿が続いても、会話がなかったと誤判定しないようにする
+            // 遡るのは新しい順に `finalize_scan_limit` 件 (1リクエスト分) までで、それより古い発言は見ない
+            _ => {
+                let messages = thread_channel_id
+                    .messages(&ctx, |f| {
+                        f.limit(self.app_config.discord.finalize_scan_limit.into());
+                        f
+                    })
+                    .await
+                    .context("メッセージ取得に失敗")?;
+                decide_finalization(&messages, bot, EMPTY_THREAD_MESSAGE_THRESHOLD)
+            }
         };
 
-        // メッセージが2件(Botが最初に投稿するメッセージ)以下だったらスレッドを削除するフラグ
-        let should_delete_thread = messages.len() <= 2;
-        // 最新の5件に人間のメッセージがなければ議題メッセージを削除するフラグ
-        let should_delete_agenda_message = !messages.iter().any(|m| !m.author.bot);
+        // 会話がなかった場合は、設定されたメッセージを後始末の前に投稿する
+        if let (FinalizationDecision::Delete, Some(content)) =
+            (&decision, &self.app_config.discord.empty_session_message)
+        {
+            match thread_channel_id
+                .send_message(ctx, |m| {
+                    m.content(content);
+                    m
+                })
+                .await
+            {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("会話がなかったVCのメッセージの投稿に失敗: {:?}", why);
+                }
+            }
+        }
 
         // スレッドを消す予定がない場合は、通話時間等を記録する
-        if !should_delete_thread {
+        if decision != FinalizationDecision::Delete {
             // メンバー取得
             let members = thread_channel_id
                 .get_thread_members(&ctx)
@@ -509,26 +3065,32 @@ impl Handler {
                     let duration = now - created_at;
                     duration.hhmmss()
                 })
-                .unwrap_or("--:--:--".to_string());
+                // スレッドの作成時刻が取得できなければ、セッションの開始時刻から計算
+                .unwrap_or_else(|| session.start_time.elapsed().hhmmss());
 
-            // Botを取得
-            let bot = &self
-                .bot_user_id
-                .lock()
-                .await
-                .context("自身のBotユーザーの取得に失敗")?;
+            // 開始/終了時刻を設定のタイムゾーンで表示する
+            let started_at = thread_created_at
+                .map(|created_at| created_at.with_timezone(&Utc))
+                .unwrap_or_else(|| {
+                    Utc::now()
+                        - chrono::Duration::from_std(session.start_time.elapsed())
+                            .unwrap_or_else(|_| chrono::Duration::zero())
+                });
+            let period = self.format_local_period(session.guild_id, started_at, Utc::now());
 
-            // 参加者リストを作成
-            let member_mentions = members
+            // 参加者リストを作成 (ユーザー名を表示する)
+            let mut member_names = vec![];
+            for user_id in members
                 .iter()
                 .filter_map(|m| m.user_id)
-                .filter(|m| m != bot)
-                .map(|m| m.mention().to_string())
-                .collect::<Vec<_>>()
-                .join(" ");
+                .filter(|m| *m != bot)
+            {
+                member_names.push(format_user(user_id, ctx).await);
+            }
+            let member_mentions = member_names.join(" ");
 
-            // 議題メッセージを消すか
-            if should_delete_agenda_message {
+            // 議題メッセージを消すか (既に削除されている場合も新たに投稿する)
+            if decision == FinalizationDecision::Archive || message.is_none() {
                 // 議題メッセージを消す場合は新たに投稿する
                 match thread_channel_id
                     .send_message(ctx, |m| {
@@ -537,9 +3099,11 @@ impl Handler {
                             f.title("VCが終了しました");
                             f.description(format!("`{}` のVCが終了しました", &thread_name));
                             f.field("通話時間", duration, true);
+                            f.field("時間帯", &period, true);
                             f.field("参加者", member_mentions, false);
                             f
                         });
+                        m.components(resume_button);
                         m.allowed_mentions(|m| m.empty_users());
                         m
                     })
@@ -551,7 +3115,17 @@ impl Handler {
                         error!("VC解散時に議題メッセージを削除できませんでした: {:?}", why);
                     }
                 };
-            } else {
+            } else if let Some(message) = message.as_mut() {
+                // VC作成時に付けたリアクションを外す
+                if let Some(reaction) = self.vc_creation_reaction() {
+                    match message.delete_reaction_emoji(ctx, reaction).await {
+                        Ok(_) => {}
+                        Err(why) => {
+                            error!("議題メッセージのリアクションの削除に失敗: {:?}", why);
+                        }
+                    }
+                }
+
                 // 議題メッセージを編集
                 match message
                     .edit(ctx, |m| {
@@ -560,9 +3134,11 @@ impl Handler {
                             f.title(&thread_name);
                             f.description(format!("`{}` のVCが終了しました", &thread_name));
                             f.field("通話時間", duration, true);
+                            f.field("時間帯", &period, true);
                             f.field("参加者", member_mentions, false);
                             f
                         });
+                        m.components(resume_button);
                         m.allowed_mentions(|m| m.empty_users());
                         m
                     })
@@ -577,8 +3153,8 @@ impl Handler {
             }
         }
 
-        // 最新の5件に人間のメッセージがなければ議題メッセージを削除
-        if should_delete_agenda_message {
+        // 議題メッセージを書き換えない場合は議題メッセージを削除
+        if let (true, Some(message)) = (decision != FinalizationDecision::Edit, &message) {
             // メッセージがあれば議題メッセージを削除
             match message.delete(&ctx).await {
                 Ok(_) => {}
@@ -586,35 +3162,317 @@ impl Handler {
                     // メッセージが削除できなくてもチャンネルをアーカイブしたいので、ログを出力だけしておく
                     error!("VC解散時に議題メッセージを削除できませんでした: {:?}", why);
                 }
-            };
+            };
+        }
+
+        Ok(decision == FinalizationDecision::Delete)
+    }
+
+    /// VCに参加/退出した時の処理
+    async fn handle_voice_state_update(
+        &self,
+        ctx: &Context,
+        old: Option<VoiceState>,
+        new: VoiceState,
+    ) {
+        // 別のVCへ移動/退出した場合は、元のVCのメンバーから削除
+        if let Some(old_channel_id) = old.as_ref().and_then(|s| s.channel_id) {
+            if Some(old_channel_id) != new.channel_id {
+                if let Some(members) = self.vc_to_members.lock().await.get_mut(&old_channel_id) {
+                    members.remove(&new.user_id);
+                }
+
+                // 別のVCへ移動した場合は、元のVCのスレッドに退出メッセージを送る
+                if new.channel_id.is_some() {
+                    match self
+                        .post_leave_message(ctx, &old_channel_id, new.user_id, new.member.as_ref())
+                        .await
+                    {
+                        Ok(_) | Err(BotError::SessionNotFound) => {}
+                        Err(why) => {
+                            error!("退出メッセージの送信に失敗: {:?}", why);
+                        }
+                    }
+                }
+
+                // 元のVCの議題メッセージの人数を更新
+                match self.update_agenda_member_count(ctx, &old_channel_id).await {
+                    Ok(_) | Err(BotError::SessionNotFound) => {}
+                    Err(why) => {
+                        error!("議題メッセージの人数の更新に失敗: {:?}", why);
+                    }
+                }
+
+                // 元のVCのスレッド名の人数を更新
+                match self
+                    .update_thread_name_member_count(ctx, &old_channel_id)
+                    .await
+                {
+                    Ok(_) | Err(BotError::SessionNotFound) => {}
+                    Err(why) => {
+                        error!("スレッド名の人数の更新に失敗: {:?}", why);
+                    }
+                }
+
+                // 元のVCの人数が減っていれば、次に満員に近づいた時に再び警告できるようにする
+                match self.check_user_limit(ctx, &old_channel_id).await {
+                    Ok(_) | Err(BotError::SessionNotFound) => {}
+                    Err(why) => {
+                        error!("VCの人数上限の確認に失敗: {:?}", why);
+                    }
+                }
+            }
+        }
+
+        // チャンネルID、ユーザーが存在しない場合は無視
+        if let (Some(vc_channel_id), Some(member)) = (new.channel_id, new.member) {
+            // 無視するユーザーの場合は無視
+            if self.is_ignored_member(&member) {
+                return;
+            }
+
+            // チャンネルを取得
+            let vc_channel = match get_channel(ctx, vc_channel_id)
+                .await
+                .context("チャンネル取得失敗")
+                .and_then(|c| c.guild().ok_or(anyhow::anyhow!("チャンネルが存在しません")))
+            {
+                Ok(channel) => channel,
+                Err(why) => {
+                    error!("チャンネルの取得に失敗: {:?}", why);
+                    return;
+                }
+            };
+
+            // カスタムVCでない場合は無視
+            if !self.is_custom_vc(&vc_channel) {
+                return;
+            }
+
+            // スレッドが未作成で、人数が集まっていない場合は作成を保留する
+            let min_members = self.app_config.discord.min_members_to_create_thread;
+            if min_members > 1 && self.session_for_vc(&vc_channel_id).await.is_none() {
+                // VCのメンバーを取得
+                let mut members = match vc_channel.members(&ctx.cache).await {
+                    Ok(members) => members,
+                    Err(why) => {
+                        error!("VCのメンバー取得に失敗: {:?}", why);
+                        return;
+                    }
+                };
+
+                // 人数が足りない場合は保留
+                if members.len() < min_members {
+                    // 最初に参加したユーザーは記録しておく
+                    self.vc_session_creator
+                        .lock()
+                        .await
+                        .entry(vc_channel_id)
+                        .or_insert(member.user.id);
+                    self.vc_pending.lock().await.insert(vc_channel_id);
+                    return;
+                }
+
+                // 保留していた場合は、VCにいる全員分のスレッド作成/参加メッセージを送る
+                if self.vc_pending.lock().await.remove(&vc_channel_id) {
+                    // 最初に参加したユーザーが作成者になるよう先頭にする
+                    let creator = self
+                        .vc_session_creator
+                        .lock()
+                        .await
+                        .get(&vc_channel_id)
+                        .copied();
+                    members.sort_by_key(|m| Some(m.user.id) != creator);
+
+                    for pending_member in members.iter().filter(|m| !self.is_ignored_member(m)) {
+                        match self
+                            .create_or_mention_thread(ctx, &vc_channel_id, pending_member)
+                            .await
+                        {
+                            Ok(_) => {}
+                            Err(why) => {
+                                error!("VCスレッドチャンネルの作成/投稿に失敗: {:?}", why);
+                                self.notify_thread_failure(ctx, &vc_channel_id, &why).await;
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+
+            // VCスレッドチャンネルを作成
+            match self
+                .create_or_mention_thread(ctx, &vc_channel_id, &member)
+                .await
+            {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("VCスレッドチャンネルの作成/投稿に失敗: {:?}", why);
+                    self.notify_thread_failure(ctx, &vc_channel_id, &why).await;
+                    return;
+                }
+            }
+
+            // 議題メッセージの人数を更新
+            match self.update_agenda_member_count(ctx, &vc_channel_id).await {
+                Ok(_) | Err(BotError::SessionNotFound) => {}
+                Err(why) => {
+                    error!("議題メッセージの人数の更新に失敗: {:?}", why);
+                    return;
+                }
+            }
+
+            // スレッド名の人数を更新
+            match self
+                .update_thread_name_member_count(ctx, &vc_channel_id)
+                .await
+            {
+                Ok(_) | Err(BotError::SessionNotFound) => {}
+                Err(why) => {
+                    error!("スレッド名の人数の更新に失敗: {:?}", why);
+                    return;
+                }
+            }
+
+            // VCが満員に近づいていれば警告する
+            match self.check_user_limit(ctx, &vc_channel_id).await {
+                Ok(_) | Err(BotError::SessionNotFound) => {}
+                Err(why) => {
+                    error!("VCの人数上限の確認に失敗: {:?}", why);
+                }
+            }
         }
-
-        Ok(should_delete_thread)
     }
 }
 
 #[async_trait]
 impl EventHandler for Handler {
     /// 準備完了時に呼ばれる
-    async fn ready(&self, _ctx: Context, data_about_bot: Ready) {
+    #[tracing::instrument(skip_all, fields(event = "ready"))]
+    async fn ready(&self, ctx: Context, data_about_bot: Ready) {
         warn!("Bot準備完了: {}", data_about_bot.user.tag());
 
         // Bot自身のIDを取得
-        let mut bot_user_id = self.bot_user_id.lock().await;
-        *bot_user_id = Some(data_about_bot.user.id.clone());
+        // 再接続時は既に記録されているので無視する
+        let _ = self.bot_user_id.set(data_about_bot.user.id);
+
+        // スレッドを作成するチャンネルが正しく設定されているかチェック
+        match self.validate_thread_channel(&ctx).await {
+            Ok(_) => {
+                self.startup_failed.store(false, Ordering::SeqCst);
+            }
+            Err(why) => {
+                error!(
+                    "thread_channel または vc_thread_channel_overrides の設定が不正なため、イベントを処理しません。設定を見直して再起動してください: {:?}",
+                    why
+                );
+                self.startup_failed.store(true, Ordering::SeqCst);
+            }
+        }
+
+        // スラッシュコマンドを登録
+        let dev_guild_id = self.app_config.discord.dev_guild_id;
+        match self.app_config.discord.command_scope {
+            CommandScope::Guild => {
+                for guild in &data_about_bot.guilds {
+                    // 担当外のサーバーと、開発用サーバー(後で登録する)は飛ばす
+                    if !self.handles_guild(Some(guild.id)) || Some(guild.id) == dev_guild_id {
+                        continue;
+                    }
+                    match self.register_guild_commands(&ctx, guild.id).await {
+                        Ok(_) => {}
+                        Err(why) => {
+                            error!("スラッシュコマンドの登録に失敗: {:?}", why);
+                        }
+                    }
+                }
+            }
+            CommandScope::Global => match self.register_global_commands(&ctx).await {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("グローバルなスラッシュコマンドの登録に失敗: {:?}", why);
+                }
+            },
+        }
+        // 開発用サーバーには、すぐに反映されるよう常にサーバーのコマンドとして登録する
+        if let Some(dev_guild_id) = dev_guild_id {
+            match self.register_guild_commands(&ctx, dev_guild_id).await {
+                Ok(_) => {}
+                Err(why) => {
+                    error!(
+                        "開発用サーバーへのスラッシュコマンドの登録に失敗: {:?}",
+                        why
+                    );
+                }
+            }
+        }
+
+        // 準備完了にし、それまでに届いていたイベントを取り出す
+        let pending_events = {
+            let mut ready_complete = self.ready_complete.lock().await;
+            *ready_complete = true;
+            std::mem::take(&mut *self.pending_events.lock().await)
+        };
+
+        // 準備完了前に届いていたイベントを処理
+        for (old, new) in pending_events {
+            self.handle_voice_state_update(&ctx, old, new).await;
+        }
+
+        // 予約されたスレッドの更新を定期的にまとめて行う (再接続時に多重に起動しないようにする)
+        // ready のスパンに紐づかないよう、独立したタスクとして起動する
+        if !self.thread_update_task_started.swap(true, Ordering::SeqCst) {
+            let handler = self.clone();
+            let thread_update_ctx = ctx.clone();
+            tokio::spawn(async move {
+                loop {
+                    sleep(THREAD_UPDATE_INTERVAL).await;
+                    handler.flush_thread_updates(&thread_update_ctx).await;
+                }
+            });
+
+            // ライブ転送を待っている発言を定期的にまとめて転送する
+            if self.app_config.discord.live_transcript_channel.is_some() {
+                let handler = self.clone();
+                tokio::spawn(async move {
+                    loop {
+                        sleep(TRANSCRIPT_MIN_INTERVAL).await;
+                        handler.flush_transcript(&ctx).await;
+                    }
+                });
+            }
+        }
     }
 
     /// VCで話すボタンが押された時
+    #[tracing::instrument(skip_all, fields(event = "interaction_create", guild_id = ?interaction_guild_id(&interaction)))]
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        // 起動時の設定チェックに失敗している場合や、担当外のサーバーの場合は無視
+        if self.is_startup_failed() || !self.handles_guild(interaction_guild_id(&interaction)) {
+            return;
+        }
+
         // 不明なインタラクションは無視
         match interaction {
             Interaction::MessageComponent(interaction)
-                if interaction.data.custom_id == "rename_button" =>
+                if interaction.data.custom_id == RESUME_BUTTON_ID =>
             {
-                // 名前変更チェック&反応
+                // 再開の権限チェック&スレッドの再開
+                match self.resume_button_pressed(&ctx, &interaction).await {
+                    Ok(_) => {}
+                    Err(why) => {
+                        self.count_api_error(&why);
+                        error!("インタラクションの処理に失敗: {:?}", why);
+                        return;
+                    }
+                }
+            }
+            Interaction::MessageComponent(interaction) => {
+                // ボタンの権限チェック&反応
                 match self.button_pressed(&ctx, &interaction).await {
                     Ok(_) => {}
                     Err(why) => {
+                        self.count_api_error(&why);
                         error!("インタラクションの処理に失敗: {:?}", why);
                         return;
                     }
@@ -627,134 +3485,537 @@ impl EventHandler for Handler {
                 match self.rename_vc(&ctx, &interaction).await {
                     Ok(_) => {}
                     Err(why) => {
+                        self.count_api_error(&why);
                         error!("インタラクションの処理に失敗: {:?}", why);
                         return;
                     }
                 }
             }
+            Interaction::Autocomplete(interaction) => {
+                // スラッシュコマンドの入力候補を返す
+                match self.autocomplete(&ctx, &interaction).await {
+                    Ok(_) => {}
+                    Err(why) => {
+                        self.count_api_error(&why);
+                        error!("入力候補の処理に失敗: {:?}", why);
+                        return;
+                    }
+                }
+            }
+            Interaction::ApplicationCommand(interaction) => {
+                // スラッシュコマンドを実行
+                match self.command_invoked(&ctx, &interaction).await {
+                    Ok(_) => {}
+                    Err(why) => {
+                        self.count_api_error(&why);
+                        error!("コマンドの処理に失敗: {:?}", why);
+                        return;
+                    }
+                }
+            }
             _ => return,
         };
     }
 
-    /// VC削除時
-    async fn channel_delete(&self, ctx: Context, vc_channel: &GuildChannel) {
-        // カスタムVCでない場合は無視
-        if !self.is_custom_vc(vc_channel) {
+    /// メッセージ投稿時
+    #[tracing::instrument(skip_all, fields(event = "message", guild_id = ?new_message.guild_id, channel_id = %new_message.channel_id))]
+    async fn message(&self, ctx: Context, new_message: Message) {
+        // Botの発言や担当外のサーバーでの発言は無視
+        if new_message.author.bot || !self.handles_guild(new_message.guild_id) {
             return;
         }
 
-        // マップからスレッドのチャンネルIDを取得
-        // 一度変数に入れてからmatchにいれないとロックされっぱなしになる
-        let thread_channel_id = self
-            .vc_to_thread
+        // VCのスレッドでの発言であれば発言数を数える
+        let is_session_thread = match self
+            .sessions
             .lock()
             .await
-            .get(&vc_channel.id)
-            .map(|c| c.clone());
-
-        // チャンネルIDが見つけれなければ終了
-        let thread_channel_id = match thread_channel_id {
-            Some(channel_id) => channel_id,
-            None => return,
+            .values_mut()
+            .filter_map(ThreadState::session_mut)
+            .find(|s| s.thread_channel_id == new_message.channel_id)
+        {
+            Some(session) => {
+                session.message_count += 1;
+                true
+            }
+            None => false,
         };
 
-        // VCで誰も喋ってなかったら議題メッセージを削除
-        let should_delete = match self.finalize_agenda_message(&ctx, &thread_channel_id).await {
-            Ok(del) => del,
+        // VCのスレッドでの発言であれば時刻を記録し、設定で有効な場合はライブ転送する
+        if is_session_thread {
+            self.thread_last_human_message
+                .lock()
+                .await
+                .insert(new_message.channel_id, Instant::now());
+
+            self.forward_to_transcript(&ctx, &new_message).await;
+        }
+    }
+
+    /// メッセージ削除時
+    #[tracing::instrument(skip_all, fields(event = "message_delete", guild_id = ?guild_id, channel_id = %channel_id))]
+    async fn message_delete(
+        &self,
+        ctx: Context,
+        channel_id: ChannelId,
+        deleted_message_id: MessageId,
+        guild_id: Option<GuildId>,
+    ) {
+        // 担当外のサーバーの場合は無視
+        if !self.handles_guild(guild_id) {
+            return;
+        }
+
+        // 議題メッセージが削除されていたら記録から外す
+        self.forget_agenda_message(channel_id, deleted_message_id)
+            .await;
+
+        // 参加メッセージが削除されていたら再作成
+        match self
+            .recreate_welcome_message(&ctx, channel_id, deleted_message_id)
+            .await
+        {
+            Ok(_) => {}
             Err(why) => {
-                error!(
-                    "VCチャンネルで会話がなかったが、議題メッセージ削除に失敗: {:?}",
-                    why
-                );
-                false
+                error!("参加メッセージの再作成に失敗: {:?}", why);
+                return;
             }
-        };
+        }
+    }
 
-        // 2件以上のメッセージがなければスレッドを削除する
-        if should_delete {
-            // VCスレッドチャンネルを削除
-            match thread_channel_id.delete(&ctx).await {
-                Ok(_) => {}
-                Err(why) => {
-                    error!("VCスレッドチャンネルの削除に失敗: {:?}", why);
-                    return;
-                }
+    /// VC作成時
+    #[tracing::instrument(skip_all, fields(event = "channel_create", guild_id = %channel.guild_id, vc_id = %channel.id))]
+    async fn channel_create(&self, ctx: Context, channel: &GuildChannel) {
+        // 起動時の設定チェックに失敗している場合や、担当外のサーバーの場合は無視
+        if self.is_startup_failed() || !self.handles_guild(Some(channel.guild_id)) {
+            return;
+        }
+
+        // カスタムVCでない場合は無視
+        if !self.is_custom_vc(channel) {
+            return;
+        }
+        info!("VCが作成されました: {} ({})", channel.name, channel.id);
+
+        // 設定で有効な場合は、誰かが参加する前にスレッドを作成するチャンネルで知らせる
+        if !self.app_config.discord.announce_new_vc {
+            return;
+        }
+        let thread_channel = self.bot_config.read().await.thread_channel_for(
+            &channel.id,
+            self.app_config.discord.thread_channel_for(channel.guild_id),
+        );
+        match thread_channel
+            .send_message(&ctx, |m| {
+                m.content(format!(
+                    "新しいVC `{}` が作成されました！\nVCに参加する→ {}",
+                    channel.name.replace('`', ""),
+                    channel.mention()
+                ))
+            })
+            .await
+        {
+            Ok(_) => {}
+            Err(why) => {
+                error!("VCの作成の告知に失敗: {:?}", why);
             }
-        } else {
-            // VCスレッドチャンネルをアーカイブ
-            match thread_channel_id
-                .edit_thread(ctx, |t| {
-                    t.archived(true);
-                    t
-                })
-                .await
-            {
-                Ok(_) => {}
-                Err(why) => {
-                    error!("VCスレッドチャンネルのアーカイブに失敗: {:?}", why);
-                    return;
-                }
+        }
+    }
+
+    /// VC削除時
+    #[tracing::instrument(skip_all, fields(event = "channel_delete", guild_id = %vc_channel.guild_id, vc_id = %vc_channel.id))]
+    async fn channel_delete(&self, ctx: Context, vc_channel: &GuildChannel) {
+        // 起動時の設定チェックに失敗している場合や、担当外のサーバーの場合は無視
+        if self.is_startup_failed() || !self.handles_guild(Some(vc_channel.guild_id)) {
+            return;
+        }
+
+        // カスタムVCでない場合は無視
+        if !self.is_custom_vc(vc_channel) {
+            return;
+        }
+
+        // VCの挨拶を削除し、セッションを終了する
+        self.vc_custom_greetings.lock().await.remove(&vc_channel.id);
+        self.end_session(&ctx, &vc_channel.id, SessionEndReason::VcDeleted)
+            .await;
+    }
+
+    /// イベントの状態が変わった時
+    #[tracing::instrument(skip_all, fields(event = "guild_scheduled_event_update", guild_id = %event.guild_id, channel_id = ?event.channel_id))]
+    async fn guild_scheduled_event_update(&self, ctx: Context, event: ScheduledEvent) {
+        // 設定で無効な場合や、起動時の設定チェックに失敗している場合、担当外のサーバーの場合は無視
+        if !self.app_config.discord.integrate_with_events
+            || self.is_startup_failed()
+            || !self.handles_guild(Some(event.guild_id))
+        {
+            return;
+        }
+
+        match self.scheduled_event_updated(&ctx, &event).await {
+            Ok(_) => {}
+            Err(why) => {
+                error!("イベントのスレッドの作成/後始末に失敗: {:?}", why);
             }
         }
     }
 
+    /// サーバーからメンバーが抜けた時 (Bot自身がキックされた場合は、そのサーバーの記録を削除する)
+    #[tracing::instrument(skip_all, fields(event = "guild_member_removal", guild_id = %guild_id))]
+    async fn guild_member_removal(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        user: User,
+        _member_data_if_available: Option<Member>,
+    ) {
+        if self.bot_user_id.get() != Some(&user.id) || !self.handles_guild(Some(guild_id)) {
+            return;
+        }
+
+        let channel_ids = ctx
+            .cache
+            .guild(guild_id)
+            .map(|guild| guild.channels.keys().copied().collect())
+            .unwrap_or_default();
+        self.forget_guild(guild_id, channel_ids).await;
+    }
+
+    /// サーバーから削除された時/サーバーが利用できなくなった時
+    #[tracing::instrument(skip_all, fields(event = "guild_delete", guild_id = %incomplete.id))]
+    async fn guild_delete(&self, _ctx: Context, incomplete: UnavailableGuild, full: Option<Guild>) {
+        if !self.handles_guild(Some(incomplete.id)) {
+            return;
+        }
+
+        // 障害で一時的に利用できないだけの場合は、復旧後も続けられるよう記録を残す
+        if incomplete.unavailable {
+            warn!(
+                "サーバー({})が一時的に利用できなくなりました",
+                incomplete.id
+            );
+            return;
+        }
+
+        let channel_ids = full
+            .map(|guild| guild.channels.keys().copied().collect())
+            .unwrap_or_default();
+        self.forget_guild(incomplete.id, channel_ids).await;
+    }
+
     /// VC名更新時
-    async fn channel_update(&self, _ctx: Context, _old: Option<Channel>, new: Channel) {
+    #[tracing::instrument(skip_all, fields(event = "channel_update", channel_id = %new.id()))]
+    async fn channel_update(&self, ctx: Context, old: Option<Channel>, new: Channel) {
+        // 起動時の設定チェックに失敗している場合は無視
+        if self.is_startup_failed() {
+            return;
+        }
+
         // チャンネルを取得
         let vc_channel = match new.guild() {
             Some(guild) => guild,
             None => return,
         };
 
+        // 担当外のサーバーの場合は無視
+        if !self.handles_guild(Some(vc_channel.guild_id)) {
+            return;
+        }
+
         // カスタムVCでない場合は無視
         if !self.is_custom_vc(&vc_channel) {
             return;
         }
 
-        // VCスレッドチャンネルをリネーム
-        match self.rename_thread(&_ctx, &vc_channel.id).await {
-            Ok(_) => {}
-            Err(why) => {
-                error!("VCスレッドチャンネルのリネームに失敗: {:?}", why);
-                return;
+        let old_channel = old.and_then(|c| c.guild());
+
+        // 設定で有効な場合は、VCのステータスの変更をスレッドに知らせる
+        // 変更前のチャンネルがキャッシュになければ、変わったかどうか分からないので知らせない
+        if self.app_config.discord.sync_vc_status_to_thread {
+            if let (Some(old_channel), Some(new_topic)) = (&old_channel, &vc_channel.topic) {
+                if old_channel.topic.as_ref() != Some(new_topic) && !new_topic.is_empty() {
+                    self.post_to_session_thread(
+                        &ctx,
+                        &vc_channel.id,
+                        format!("VCのステータスが更新されました: {}", new_topic),
+                    )
+                    .await;
+                }
+            }
+        }
+
+        // 設定で有効な場合は、VCの人数制限の変更をスレッドに知らせる (制限なしは0人とする)
+        if self.app_config.discord.notify_user_limit_changes {
+            if let Some(old_channel) = &old_channel {
+                let old_limit = old_channel.user_limit.unwrap_or(0);
+                let new_limit = vc_channel.user_limit.unwrap_or(0);
+                if old_limit != new_limit {
+                    self.post_to_session_thread(
+                        &ctx,
+                        &vc_channel.id,
+                        format!(
+                            "VCの人数制限が{}人から{}人に変更されました",
+                            old_limit, new_limit
+                        ),
+                    )
+                    .await;
+                }
             }
         }
+
+        // 名前が変わっていない場合は無視 (ビットレートや人数制限、権限の変更など)
+        // 変更前のチャンネルがキャッシュになければ、念のためリネームする
+        let old_name = old_channel.map(|g| g.name);
+        if old_name.as_ref() == Some(&vc_channel.name) {
+            return;
+        }
+
+        // VCスレッドチャンネルのリネームを予約
+        if let Some(session) = self.session_for_vc(&vc_channel.id).await {
+            self.schedule_thread_update(session.thread_channel_id).await;
+        }
     }
 
     /// VCに参加/退出した時
-    async fn voice_state_update(&self, ctx: Context, _old: Option<VoiceState>, new: VoiceState) {
-        // チャンネルID、ユーザーが存在しない場合は無視
-        if let (Some(vc_channel_id), Some(member)) = (new.channel_id, new.member) {
-            // チャンネルを取得
-            let vc_channel = match vc_channel_id
-                .to_channel(&ctx)
-                .await
-                .context("チャンネル取得失敗")
-                .and_then(|c| c.guild().ok_or(anyhow::anyhow!("チャンネルが存在しません")))
-            {
-                Ok(channel) => channel,
-                Err(why) => {
-                    error!("チャンネルの取得に失敗: {:?}", why);
-                    return;
-                }
-            };
+    #[tracing::instrument(skip_all, fields(event = "voice_state_update", guild_id = ?new.guild_id, vc_id = ?new.channel_id))]
+    async fn voice_state_update(&self, ctx: Context, old: Option<VoiceState>, new: VoiceState) {
+        // 起動時の設定チェックに失敗している場合や、担当外のサーバーの場合は無視
+        if self.is_startup_failed() || !self.handles_guild(new.guild_id) {
+            return;
+        }
 
-            // カスタムVCでない場合は無視
-            if !self.is_custom_vc(&vc_channel) {
-                return;
-            }
+        // VCを移動していなければ、参加/退出の処理は不要なのでロックを取る前に終える
+        // (ミュート状態は参加メッセージにのみ表示するので、切り替えだけの更新も処理しない)
+        if is_same_channel_update(old.as_ref(), &new) {
+            return;
+        }
 
-            // VCスレッドチャンネルを作成
-            match self
-                .create_or_mention_thread(&ctx, &vc_channel_id, &member)
-                .await
-            {
-                Ok(_) => {}
-                Err(why) => {
-                    error!("VCスレッドチャンネルの作成/投稿に失敗: {:?}", why);
-                    return;
-                }
+        // 準備完了前に届いたイベントは、準備完了後に処理する
+        {
+            let ready_complete = self.ready_complete.lock().await;
+            if !*ready_complete {
+                self.pending_events.lock().await.push_back((old, new));
+                return;
             }
         }
+
+        self.handle_voice_state_update(&ctx, old, new).await;
+    }
+}
+
+#[cfg(test)]
+mod test_fixtures;
+
+#[cfg(all(test, feature = "integration-tests"))]
+mod integration_tests;
+
+#[cfg(test)]
+mod tests {
+    use super::test_fixtures::message;
+    use super::*;
+
+    const BOT_ID: UserId = UserId(100);
+
+    /// テスト用のボイス状態を作成する
+    fn voice_state(channel_id: Option<u64>) -> VoiceState {
+        test_fixtures::voice_state(None, channel_id, 1)
+    }
+
+    #[test]
+    fn mute_in_same_channel_is_same_channel_update() {
+        let old = voice_state(Some(1));
+        assert!(is_same_channel_update(Some(&old), &voice_state(Some(1))));
+    }
+
+    #[test]
+    fn display_name_prefers_nick_then_global_name() {
+        let mut user: User = serde_json::from_value(test_fixtures::user_json(1, false)).unwrap();
+        assert_eq!(display_name(None, &user), "user1");
+        user.global_name = Some("表示名".to_string());
+        assert_eq!(display_name(None, &user), "表示名");
+        assert_eq!(display_name(Some("ニックネーム"), &user), "ニックネーム");
+    }
+
+    #[test]
+    fn mute_and_deafen_toggles_are_same_channel_update() {
+        let old = voice_state(Some(1));
+        let mut muted = voice_state(Some(1));
+        muted.self_mute = true;
+        assert!(is_same_channel_update(Some(&old), &muted));
+        let mut deafened = muted.clone();
+        deafened.self_deaf = true;
+        assert!(is_same_channel_update(Some(&muted), &deafened));
+    }
+
+    #[test]
+    fn join_and_move_are_not_same_channel_update() {
+        assert!(!is_same_channel_update(None, &voice_state(Some(1))));
+        let old = voice_state(Some(1));
+        assert!(!is_same_channel_update(Some(&old), &voice_state(Some(2))));
+        assert!(!is_same_channel_update(Some(&old), &voice_state(None)));
+    }
+
+    #[test]
+    fn creation_nonce_is_stable_across_restarts() {
+        // 再起動後の再送でも同じnonceになるよう、値そのものを固定しておく
+        let started_at = DateTime::parse_from_rfc3339("2022-08-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            creation_nonce(&ChannelId(1), started_at),
+            "55af79c94d0a56c8ad1bf8561"
+        );
+    }
+
+    #[test]
+    fn creation_nonce_differs_per_vc_and_session() {
+        let started_at = DateTime::parse_from_rfc3339("2022-08-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let nonce = creation_nonce(&ChannelId(1), started_at);
+        assert_ne!(nonce, creation_nonce(&ChannelId(2), started_at));
+        assert_ne!(
+            nonce,
+            creation_nonce(
+                &ChannelId(1),
+                started_at + chrono::Duration::milliseconds(1)
+            )
+        );
+        assert!(nonce.len() <= MESSAGE_NONCE_MAX_CHARS);
+    }
+
+    #[test]
+    fn enforced_nonce_sets_nonce_and_enforce_flag() {
+        let mut m = CreateMessage::default();
+        m.enforced_nonce("abc");
+        assert_eq!(m.0.get("nonce"), Some(&json!("abc")));
+        assert_eq!(m.0.get("enforce_nonce"), Some(&json!(true)));
+    }
+
+    #[test]
+    fn all_bot_messages_are_deleted() {
+        let messages = vec![message(100, true), message(100, true)];
+        assert_eq!(
+            decide_finalization(&messages, BOT_ID, EMPTY_THREAD_MESSAGE_THRESHOLD),
+            FinalizationDecision::Delete
+        );
+    }
+
+    #[test]
+    fn zero_messages_are_deleted() {
+        assert_eq!(
+            decide_finalization(&[], BOT_ID, EMPTY_THREAD_MESSAGE_THRESHOLD),
+            FinalizationDecision::Delete
+        );
+    }
+
+    #[test]
+    fn exactly_threshold_bot_messages_are_deleted() {
+        let messages = vec![message(100, true), message(200, true)];
+        assert_eq!(
+            decide_finalization(&messages, BOT_ID, 2),
+            FinalizationDecision::Delete
+        );
+    }
+
+    #[test]
+    fn human_message_within_threshold_is_kept() {
+        let messages = vec![message(1, false), message(100, true)];
+        assert_eq!(
+            decide_finalization(&messages, BOT_ID, 2),
+            FinalizationDecision::Edit
+        );
+    }
+
+    #[test]
+    fn early_human_message_followed_by_bot_messages_is_kept() {
+        let mut messages = vec![message(100, true); 10];
+        messages.push(message(1, false));
+        assert_eq!(
+            decide_finalization(&messages, BOT_ID, 2),
+            FinalizationDecision::Edit
+        );
+    }
+
+    #[test]
+    fn one_human_message_above_threshold_is_kept() {
+        let messages = vec![message(1, false), message(100, true), message(100, true)];
+        assert_eq!(
+            decide_finalization(&messages, BOT_ID, 2),
+            FinalizationDecision::Edit
+        );
+    }
+
+    #[test]
+    fn bot_messages_above_threshold_are_archived() {
+        let messages = vec![message(100, true), message(100, true), message(200, true)];
+        assert_eq!(
+            decide_finalization(&messages, BOT_ID, 2),
+            FinalizationDecision::Archive
+        );
+    }
+
+    #[test]
+    fn mixed_bot_and_human_messages_are_kept() {
+        let messages = vec![
+            message(100, true),
+            message(1, false),
+            message(200, true),
+            message(2, false),
+            message(100, true),
+        ];
+        assert_eq!(
+            decide_finalization(&messages, BOT_ID, 2),
+            FinalizationDecision::Edit
+        );
+    }
+
+    #[test]
+    fn truncate_field_keeps_within_embed_limit() {
+        assert_eq!(truncate_field("abc".to_string()), "abc");
+        let participants = vec!["<@000000000000000000>"; 60].join(" ");
+        let truncated = truncate_field(participants);
+        assert_eq!(truncated.chars().count(), EMBED_FIELD_MAX_CHARS);
+        assert!(truncated.ends_with('…'));
+    }
+
+    #[test]
+    fn sessions_being_created_count_toward_cap() {
+        let mut sessions = IndexMap::new();
+        sessions.insert(ChannelId(1), ThreadState::Creating);
+        sessions.insert(ChannelId(2), ThreadState::Creating);
+        assert_eq!(sessions_toward_cap(&sessions, &ChannelId(1)), 1);
+        assert_eq!(sessions_toward_cap(&sessions, &ChannelId(3)), 2);
+    }
+
+    #[test]
+    fn batch_lines_joins_lines_within_limit() {
+        let lines = vec!["abc".to_string(), "de".to_string(), "fgh".to_string()];
+        assert_eq!(batch_lines(&lines, 6), vec!["abc\nde", "fgh"]);
+        assert_eq!(batch_lines(&lines, 10), vec!["abc\nde\nfgh"]);
+        assert!(batch_lines(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn only_deleted_vc_waits_for_grace_period() {
+        assert!(SessionEndReason::VcDeleted.waits_for_grace_period());
+        assert!(!SessionEndReason::ForceEnded.waits_for_grace_period());
+        assert!(!SessionEndReason::EventEnded.waits_for_grace_period());
+    }
+
+    #[test]
+    fn resume_in_thread_targets_the_thread() {
+        assert_eq!(
+            resume_target_thread(ChannelId(10), true, MessageId(20)),
+            ChannelId(10)
+        );
+    }
+
+    #[test]
+    fn resume_on_agenda_message_targets_the_thread_started_from_it() {
+        assert_eq!(
+            resume_target_thread(ChannelId(10), false, MessageId(20)),
+            ChannelId(20)
+        );
     }
 }
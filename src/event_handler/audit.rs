@@ -0,0 +1,94 @@
+use log::error;
+use serenity::model::{
+    id::{ChannelId, UserId},
+    mention::Mentionable,
+};
+use serenity::prelude::*;
+use serenity::utils::Colour;
+
+use super::{Handler, EMBED_COLOUR};
+
+/// 監査ログに記録するBotの操作
+pub(super) enum AuditAction {
+    /// スレッドを作成した
+    ThreadCreated {
+        vc: ChannelId,
+        thread: ChannelId,
+        by: UserId,
+    },
+    /// スレッドをアーカイブした
+    ThreadArchived { thread: ChannelId },
+    /// スレッドを削除した
+    ThreadDeleted { thread: ChannelId },
+    /// VCの名前を変更した
+    VcRenamed {
+        old: String,
+        new: String,
+        by: UserId,
+    },
+    /// 処理に失敗した
+    Error { details: String },
+}
+
+impl AuditAction {
+    /// 埋め込みメッセージのタイトル、説明、色
+    fn describe(&self) -> (&'static str, String, Colour) {
+        match self {
+            AuditAction::ThreadCreated { vc, thread, by } => (
+                "スレッド作成",
+                format!(
+                    "{} → {} (参加者: {})",
+                    vc.mention(),
+                    thread.mention(),
+                    by.mention()
+                ),
+                EMBED_COLOUR,
+            ),
+            AuditAction::ThreadArchived { thread } => (
+                "スレッドアーカイブ",
+                thread.mention().to_string(),
+                Colour::DARK_GREY,
+            ),
+            AuditAction::ThreadDeleted { thread } => {
+                ("スレッド削除", format!("`{}`", thread), Colour::DARK_GREY)
+            }
+            AuditAction::VcRenamed { old, new, by } => (
+                "VC名変更",
+                format!("`{}` → `{}` (変更者: {})", old, new, by.mention()),
+                EMBED_COLOUR,
+            ),
+            AuditAction::Error { details } => ("エラー", details.clone(), Colour::RED),
+        }
+    }
+}
+
+impl Handler {
+    /// 監査ログチャンネルにBotの操作を記録する (未設定の場合は何もしない)
+    pub(super) async fn audit(&self, ctx: &Context, action: AuditAction) {
+        let channel_id = match self.app_config.discord.audit_log_channel {
+            Some(channel_id) => channel_id,
+            None => return,
+        };
+
+        let (title, description, colour) = action.describe();
+        match channel_id
+            .send_message(ctx, |m| {
+                m.embed(|e| {
+                    e.title(title);
+                    e.description(description);
+                    e.colour(colour);
+                    e.timestamp(chrono::Utc::now());
+                    e
+                });
+                m.allowed_mentions(|m| m.empty_users());
+                m
+            })
+            .await
+        {
+            Ok(_) => {}
+            Err(why) => {
+                error!("監査ログの送信に失敗: {:?}", why);
+            }
+        }
+    }
+}
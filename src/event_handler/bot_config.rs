@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use serenity::model::id::ChannelId;
+
+use crate::app_config::DiscordConfig;
+
+/// スレッドを自動アーカイブするまでの時間(分)としてDiscordが受け付ける値
+const AUTO_ARCHIVE_MINUTES: [u16; 4] = [60, 1440, 4320, 10080];
+
+/// 起動中に `/botconfig` で変更できる設定
+/// 設定ファイルの値を初期値とし、変更は次に始まるセッションから反映される (再起動すると設定ファイルの値に戻る)
+#[derive(Debug, Clone)]
+pub(super) struct BotConfig {
+    /// スレッドの参加メッセージの書式。`{user}` と `{channel_name}` が置き換えられる (未指定の場合は既定のメッセージ)
+    pub welcome_message_template: Option<String>,
+    /// 1回のセッションで名前を変更できる回数 (未指定の場合は無制限)
+    pub max_renames_per_session: Option<usize>,
+    /// スレッドを自動アーカイブするまでの時間(分) (未指定の場合はDiscordの既定値)
+    pub thread_auto_archive_minutes: Option<u16>,
+    /// VC→スレッドを作成するチャンネルのマップ (`/botconfig threadchannel` で変更する)
+    pub vc_thread_channel_overrides: HashMap<ChannelId, ChannelId>,
+}
+
+/// `/botconfig` で指定する設定名
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BotConfigKey {
+    WelcomeMessageTemplate,
+    MaxRenamesPerSession,
+    ThreadAutoArchiveMinutes,
+}
+
+impl BotConfigKey {
+    /// 全ての設定名
+    pub const ALL: [BotConfigKey; 3] = [
+        BotConfigKey::WelcomeMessageTemplate,
+        BotConfigKey::MaxRenamesPerSession,
+        BotConfigKey::ThreadAutoArchiveMinutes,
+    ];
+
+    /// コマンドで指定する名前
+    pub fn name(self) -> &'static str {
+        match self {
+            BotConfigKey::WelcomeMessageTemplate => "welcome_message_template",
+            BotConfigKey::MaxRenamesPerSession => "max_renames_per_session",
+            BotConfigKey::ThreadAutoArchiveMinutes => "thread_auto_archive_minutes",
+        }
+    }
+
+    /// 名前から設定名を取得する
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|key| key.name() == name)
+    }
+}
+
+impl BotConfig {
+    /// 設定ファイルの値から初期値を作る
+    pub fn new(discord: &DiscordConfig) -> Self {
+        Self {
+            welcome_message_template: None,
+            max_renames_per_session: discord.max_renames_per_session,
+            thread_auto_archive_minutes: None,
+            vc_thread_channel_overrides: discord.vc_thread_channel_overrides.clone(),
+        }
+    }
+
+    /// VCのスレッドを作成するチャンネルを取得する (個別の指定がなければ `default`)
+    pub fn thread_channel_for(&self, vc_channel_id: &ChannelId, default: ChannelId) -> ChannelId {
+        self.vc_thread_channel_overrides
+            .get(vc_channel_id)
+            .copied()
+            .unwrap_or(default)
+    }
+
+    /// 設定の値を表示用の文字列で取得する
+    pub fn get(&self, key: BotConfigKey) -> String {
+        match key {
+            BotConfigKey::WelcomeMessageTemplate => self.welcome_message_template.clone(),
+            BotConfigKey::MaxRenamesPerSession => {
+                self.max_renames_per_session.map(|v| v.to_string())
+            }
+            BotConfigKey::ThreadAutoArchiveMinutes => {
+                self.thread_auto_archive_minutes.map(|v| v.to_string())
+            }
+        }
+        .unwrap_or_else(|| "(未設定)".to_string())
+    }
+
+    /// 設定の値を変更する。`none` を指定すると未設定に戻す
+    /// 値が不正な場合は利用者に表示する理由を返す
+    pub fn set(&mut self, key: BotConfigKey, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let unset = value.eq_ignore_ascii_case("none");
+        match key {
+            BotConfigKey::WelcomeMessageTemplate => {
+                if value.is_empty() {
+                    return Err("参加メッセージを入力してください".to_string());
+                }
+                self.welcome_message_template = (!unset).then(|| value.to_string());
+            }
+            BotConfigKey::MaxRenamesPerSession => {
+                self.max_renames_per_session = if unset {
+                    None
+                } else {
+                    Some(
+                        value
+                            .parse()
+                            .map_err(|_| "0以上の整数を指定してください".to_string())?,
+                    )
+                };
+            }
+            BotConfigKey::ThreadAutoArchiveMinutes => {
+                self.thread_auto_archive_minutes = if unset {
+                    None
+                } else {
+                    match value.parse() {
+                        Ok(minutes) if AUTO_ARCHIVE_MINUTES.contains(&minutes) => Some(minutes),
+                        _ => {
+                            return Err(format!(
+                                "{:?} のいずれかを指定してください",
+                                AUTO_ARCHIVE_MINUTES
+                            ))
+                        }
+                    }
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> BotConfig {
+        BotConfig {
+            welcome_message_template: None,
+            max_renames_per_session: None,
+            thread_auto_archive_minutes: None,
+            vc_thread_channel_overrides: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn thread_channel_override_takes_priority() {
+        let mut config = config();
+        config
+            .vc_thread_channel_overrides
+            .insert(ChannelId(1), ChannelId(10));
+        assert_eq!(
+            config.thread_channel_for(&ChannelId(1), ChannelId(20)),
+            ChannelId(10)
+        );
+        assert_eq!(
+            config.thread_channel_for(&ChannelId(2), ChannelId(20)),
+            ChannelId(20)
+        );
+    }
+
+    #[test]
+    fn set_and_unset_max_renames() {
+        let mut config = config();
+        config.set(BotConfigKey::MaxRenamesPerSession, "3").unwrap();
+        assert_eq!(config.max_renames_per_session, Some(3));
+        config
+            .set(BotConfigKey::MaxRenamesPerSession, "none")
+            .unwrap();
+        assert_eq!(config.max_renames_per_session, None);
+        assert!(config
+            .set(BotConfigKey::MaxRenamesPerSession, "-1")
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_auto_archive_minutes() {
+        let mut config = config();
+        assert!(config
+            .set(BotConfigKey::ThreadAutoArchiveMinutes, "30")
+            .is_err());
+        config
+            .set(BotConfigKey::ThreadAutoArchiveMinutes, "1440")
+            .unwrap();
+        assert_eq!(config.thread_auto_archive_minutes, Some(1440));
+    }
+
+    #[test]
+    fn key_names_round_trip() {
+        for key in BotConfigKey::ALL {
+            assert_eq!(BotConfigKey::from_name(key.name()), Some(key));
+        }
+    }
+}
@@ -0,0 +1,1707 @@
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+
+use anyhow::{Context as _, Result};
+use chrono::Utc;
+use hhmmss::Hhmmss;
+use log::error;
+use serenity::builder::{CreateApplicationCommands, CreateEmbed};
+use serenity::http::HttpError;
+use serenity::model::{
+    application::{
+        command::{Command, CommandOptionType},
+        interaction::{
+            application_command::{
+                ApplicationCommandInteraction, CommandDataOption, CommandDataOptionValue,
+            },
+            autocomplete::AutocompleteInteraction,
+            InteractionResponseType,
+        },
+    },
+    id::{ChannelId, GuildId, MessageId},
+    prelude::{Channel, ChannelType},
+    Permissions,
+};
+use serenity::prelude::*;
+use serenity::Error as SerenityError;
+
+use super::bot_config::BotConfigKey;
+use super::error::BotError;
+use super::{get_channel, truncate_field, Handler, RenameResult, SessionEndReason, ThreadState};
+
+/// /renamevc の入力候補で今のVCの名前に付ける接尾辞
+const AUTOCOMPLETE_SUFFIXES: [&str; 2] = [" 🎮", " 📚"];
+
+/// 入力候補の最大数
+const MAX_AUTOCOMPLETE_SUGGESTIONS: usize = 5;
+
+impl Handler {
+    /// スラッシュコマンドをサーバーに登録する
+    pub(super) async fn register_guild_commands(
+        &self,
+        ctx: &Context,
+        guild_id: GuildId,
+    ) -> Result<()> {
+        guild_id
+            .set_application_commands(&ctx.http, build_commands)
+            .await
+            .context("スラッシュコマンドの登録に失敗")?;
+
+        Ok(())
+    }
+
+    /// スラッシュコマンドをグローバルに登録する (反映されるまで最大1時間かかる)
+    pub(super) async fn register_global_commands(&self, ctx: &Context) -> Result<()> {
+        Command::set_global_application_commands(&ctx.http, build_commands)
+            .await
+            .context("グローバルなスラッシュコマンドの登録に失敗")?;
+
+        Ok(())
+    }
+
+    /// スラッシュコマンドが実行された時
+    pub(super) async fn command_invoked(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        match interaction.data.name.as_str() {
+            "unlink" => self.unlink_command(ctx, interaction).await,
+            "stats" => self.stats_command(ctx, interaction).await,
+            "vclock" => self.vclock_command(ctx, interaction).await,
+            "moveto" => self.moveto_command(ctx, interaction).await,
+            "pinmessage" => self.pinmessage_command(ctx, interaction, true).await,
+            "unpinmessage" => self.pinmessage_command(ctx, interaction, false).await,
+            "help" => self.help_command(ctx, interaction).await,
+            "session" => self.session_command(ctx, interaction).await,
+            "forceend" => self.forceend_command(ctx, interaction).await,
+            "threadinfo" => self.threadinfo_command(ctx, interaction).await,
+            "renamevc" => self.renamevc_command(ctx, interaction).await,
+            "recreatethread" => self.recreatethread_command(ctx, interaction).await,
+            "cleanup" => self.cleanup_command(ctx, interaction).await,
+            "setvcgreeting" => self.setvcgreeting_command(ctx, interaction).await,
+            "clearvcgreeting" => self.clearvcgreeting_command(ctx, interaction).await,
+            "botconfig" => self.botconfig_command(ctx, interaction).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// スラッシュコマンドの入力候補を求められた時
+    pub(super) async fn autocomplete(
+        &self,
+        ctx: &Context,
+        interaction: &AutocompleteInteraction,
+    ) -> Result<()> {
+        match interaction.data.name.as_str() {
+            "renamevc" => self.renamevc_autocomplete(ctx, interaction).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// VCとスレッドの紐づけを解除する
+    async fn unlink_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // サーバー管理権限をチェック
+        if !has_permission(interaction, Permissions::MANAGE_GUILD) {
+            return respond_ephemeral(ctx, interaction, "❌サーバー管理権限が必要です").await;
+        }
+
+        // 対象のスレッドを取得
+        let thread_channel_id = match get_channel_option(interaction, "thread") {
+            Some(channel_id) => channel_id,
+            None => {
+                return respond_ephemeral(ctx, interaction, "❌スレッドを指定してください").await
+            }
+        };
+
+        // セッションを削除
+        let session = match self.session_for_thread(&thread_channel_id).await {
+            Some(session) => self.remove_session(&session.vc_channel_id).await,
+            None => None,
+        };
+
+        // 紐づいていなかった場合
+        if session.is_none() {
+            return respond_ephemeral(ctx, interaction, "❌そのスレッドはVCと紐づいていません")
+                .await;
+        }
+        self.orphaned_threads_cleaned
+            .fetch_add(1, Ordering::Relaxed);
+
+        // スレッドが開いていて、Bot以外のメッセージがあればアーカイブする
+        let is_open = match thread_channel_id
+            .to_channel(&ctx)
+            .await
+            .context("スレッドの取得に失敗")?
+        {
+            Channel::Guild(thread) => thread.thread_metadata.map(|m| !m.archived).unwrap_or(false),
+            _ => false,
+        };
+        if is_open {
+            let messages = thread_channel_id
+                .messages(&ctx, |f| {
+                    f.limit(3);
+                    f
+                })
+                .await
+                .context("メッセージ取得に失敗")?;
+            // メッセージが2件(Botが最初に投稿するメッセージ)より多ければアーカイブ
+            if messages.len() > 2 {
+                thread_channel_id
+                    .edit_thread(ctx, |t| {
+                        t.archived(true);
+                        t
+                    })
+                    .await
+                    .context("スレッドのアーカイブに失敗")?;
+            }
+        }
+
+        respond_ephemeral(
+            ctx,
+            interaction,
+            format!(
+                "✅{} とVCの紐づけを解除しました",
+                thread_channel_id.mention()
+            ),
+        )
+        .await
+    }
+
+    /// 削除されてしまったVCのスレッドを作り直す
+    async fn recreatethread_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // サーバー管理権限をチェック
+        if !has_permission(interaction, Permissions::MANAGE_GUILD) {
+            return respond_ephemeral(ctx, interaction, "❌サーバー管理権限が必要です").await;
+        }
+
+        // 実行したチャンネル (VCまたはそのスレッド) に対応するセッションを取得
+        let session = match self.session_for_vc(&interaction.channel_id).await {
+            Some(session) => Some(session),
+            None => self.session_for_thread(&interaction.channel_id).await,
+        };
+        let session = match session {
+            Some(session) => session,
+            None => {
+                return respond_ephemeral(
+                    ctx,
+                    interaction,
+                    "❌VCかVCのスレッド内で実行してください",
+                )
+                .await
+            }
+        };
+        let vc_channel_id = session.vc_channel_id;
+
+        // 元のスレッドがまだ残っている場合は作り直さない
+        match session.thread_channel_id.get_thread_members(&ctx).await {
+            Ok(_) => {
+                return respond_ephemeral(
+                    ctx,
+                    interaction,
+                    format!(
+                        "❌{} はまだ残っています",
+                        session.thread_channel_id.mention()
+                    ),
+                )
+                .await
+            }
+            Err(SerenityError::Http(why)) if is_not_found(&why) => {}
+            Err(why) => return Err(why).context("スレッドのメンバー取得に失敗"),
+        }
+
+        // スレッドの作成に時間がかかるので、先に応答しておく
+        interaction
+            .create_interaction_response(&ctx, |r| {
+                r.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    .interaction_response_data(|d| d.ephemeral(true))
+            })
+            .await
+            .context("コマンドの応答に失敗")?;
+
+        // 元のスレッドの作成者を優先して、VCにいるメンバーをスレッドの作成者にする
+        let creator = self
+            .vc_session_creator
+            .lock()
+            .await
+            .get(&vc_channel_id)
+            .copied();
+        let mut members = self.vc_members(ctx, &vc_channel_id).await?;
+        members.retain(|m| !self.is_ignored_member(m));
+        members.sort_by_key(|m| Some(m.user.id) != creator);
+        let member = match members.into_iter().next() {
+            Some(member) => member,
+            None => {
+                interaction
+                    .edit_original_interaction_response(&ctx, |r| {
+                        r.content("❌VCに誰もいないため、スレッドを作り直せません")
+                    })
+                    .await
+                    .context("結果の応答に失敗")?;
+                return Ok(());
+            }
+        };
+
+        // 元のスレッドの記録を削除して、スレッドを作り直す
+        self.remove_session(&vc_channel_id).await;
+        self.clear_vc_records(&vc_channel_id).await;
+        self.thread_to_welcome_message
+            .lock()
+            .await
+            .shift_remove(&session.thread_channel_id);
+        self.vc_last_thread_created
+            .lock()
+            .await
+            .remove(&vc_channel_id);
+        self.create_or_mention_thread(ctx, &vc_channel_id, &member)
+            .await?;
+
+        // 作り直したスレッドを返答
+        let content = match self.session_for_vc(&vc_channel_id).await {
+            Some(new_session) => format!(
+                "✅{} のスレッドを作り直しました: {}",
+                vc_channel_id.mention(),
+                new_session.thread_channel_id.mention()
+            ),
+            None => "❌スレッドを作り直せませんでした".to_string(),
+        };
+        interaction
+            .edit_original_interaction_response(&ctx, |r| r.content(content))
+            .await
+            .context("結果の応答に失敗")?;
+
+        Ok(())
+    }
+
+    /// 記録の中から、既に存在しないVCやスレッドの記録を探して報告する (fixを指定した場合は削除する)
+    async fn cleanup_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // サーバー管理権限をチェック
+        if !has_permission(interaction, Permissions::MANAGE_GUILD) {
+            return respond_ephemeral(ctx, interaction, "❌サーバー管理権限が必要です").await;
+        }
+        let fix = get_bool_option(interaction, "fix").unwrap_or(false);
+
+        // チャンネルの確認に時間がかかるので、先に応答しておく
+        interaction
+            .create_interaction_response(&ctx, |r| {
+                r.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    .interaction_response_data(|d| d.ephemeral(true))
+            })
+            .await
+            .context("コマンドの応答に失敗")?;
+
+        // 1) VCが存在しないセッション、2) スレッドが存在しないセッションを探す
+        let sessions = self
+            .sessions
+            .lock()
+            .await
+            .values()
+            .filter_map(ThreadState::active)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut missing_vcs = vec![];
+        let mut missing_threads = vec![];
+        for session in &sessions {
+            if !channel_exists(ctx, session.vc_channel_id).await? {
+                missing_vcs.push(session.vc_channel_id);
+            } else if !channel_exists(ctx, session.thread_channel_id).await? {
+                missing_threads.push(session.vc_channel_id);
+            }
+        }
+
+        // 3) どのセッションのスレッドでもない、スレッドごとの記録を探す
+        let session_threads = sessions
+            .iter()
+            .map(|s| s.thread_channel_id)
+            .collect::<HashSet<_>>();
+        let mut orphaned_threads = self
+            .thread_to_welcome_message
+            .lock()
+            .await
+            .keys()
+            .chain(self.thread_last_human_message.lock().await.keys())
+            .filter(|thread_id| !session_threads.contains(thread_id))
+            .copied()
+            .collect::<Vec<_>>();
+        orphaned_threads.sort();
+        orphaned_threads.dedup();
+
+        // 見つかった記録を削除
+        if fix {
+            for vc_channel_id in missing_vcs.iter().chain(&missing_threads) {
+                self.clear_vc_records(vc_channel_id).await;
+                if let Some(session) = self.remove_session(vc_channel_id).await {
+                    self.thread_to_welcome_message
+                        .lock()
+                        .await
+                        .shift_remove(&session.thread_channel_id);
+                    self.thread_last_human_message
+                        .lock()
+                        .await
+                        .remove(&session.thread_channel_id);
+                }
+            }
+            for thread_id in &orphaned_threads {
+                self.thread_to_welcome_message
+                    .lock()
+                    .await
+                    .shift_remove(thread_id);
+                self.thread_last_human_message
+                    .lock()
+                    .await
+                    .remove(thread_id);
+            }
+            let cleaned = missing_vcs.len() + missing_threads.len() + orphaned_threads.len();
+            self.orphaned_threads_cleaned
+                .fetch_add(cleaned as u64, Ordering::Relaxed);
+        }
+
+        // 報告
+        let format_ids = |ids: &[ChannelId]| {
+            if ids.is_empty() {
+                return "なし".to_string();
+            }
+            let text = ids
+                .iter()
+                .map(|id| format!("`{}`", id))
+                .collect::<Vec<_>>()
+                .join(" ");
+            truncate_field(text)
+        };
+        let found =
+            !missing_vcs.is_empty() || !missing_threads.is_empty() || !orphaned_threads.is_empty();
+        interaction
+            .edit_original_interaction_response(&ctx, |r| {
+                r.embed(|e| {
+                    e.title(match (found, fix) {
+                        (false, _) => "✅不整合な記録はありませんでした",
+                        (true, false) => {
+                            "⚠️不整合な記録が見つかりました (`fix` を指定すると削除します)"
+                        }
+                        (true, true) => "✅不整合な記録を削除しました",
+                    });
+                    e.field("VCが存在しないセッション", format_ids(&missing_vcs), false);
+                    e.field(
+                        "スレッドが存在しないセッション (VC)",
+                        format_ids(&missing_threads),
+                        false,
+                    );
+                    e.field(
+                        "セッションのないスレッドの記録",
+                        format_ids(&orphaned_threads),
+                        false,
+                    );
+                    e
+                })
+            })
+            .await
+            .context("結果の応答に失敗")?;
+
+        Ok(())
+    }
+
+    /// VCのスレッドを作成したときの参加メッセージを設定する
+    async fn setvcgreeting_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // チャンネル管理権限をチェック
+        if !has_permission(interaction, Permissions::MANAGE_CHANNELS) {
+            return respond_ephemeral(ctx, interaction, "❌チャンネル管理権限が必要です").await;
+        }
+
+        let vc_channel_id = match self.greeting_target_vc(ctx, interaction).await? {
+            Some(vc_channel_id) => vc_channel_id,
+            None => {
+                return respond_ephemeral(
+                    ctx,
+                    interaction,
+                    "❌`channel` でこのBotが管理しているVCを指定するか、VCかVCのスレッド内で実行してください",
+                )
+                .await
+            }
+        };
+        let message = match get_string_option(interaction, "message") {
+            Some(message) if !message.trim().is_empty() => message,
+            _ => {
+                return respond_ephemeral(ctx, interaction, "❌メッセージを入力してください").await
+            }
+        };
+
+        self.vc_custom_greetings
+            .lock()
+            .await
+            .insert(vc_channel_id, message);
+
+        respond_ephemeral(
+            ctx,
+            interaction,
+            format!(
+                "✅{} のスレッドを作成したときの参加メッセージを設定しました",
+                vc_channel_id.mention()
+            ),
+        )
+        .await
+    }
+
+    /// `/setvcgreeting` で設定した参加メッセージを削除する
+    async fn clearvcgreeting_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // チャンネル管理権限をチェック
+        if !has_permission(interaction, Permissions::MANAGE_CHANNELS) {
+            return respond_ephemeral(ctx, interaction, "❌チャンネル管理権限が必要です").await;
+        }
+
+        let vc_channel_id = match self.greeting_target_vc(ctx, interaction).await? {
+            Some(vc_channel_id) => vc_channel_id,
+            None => {
+                return respond_ephemeral(
+                    ctx,
+                    interaction,
+                    "❌`channel` でこのBotが管理しているVCを指定するか、VCかVCのスレッド内で実行してください",
+                )
+                .await
+            }
+        };
+
+        let removed = self
+            .vc_custom_greetings
+            .lock()
+            .await
+            .remove(&vc_channel_id)
+            .is_some();
+        let content = if removed {
+            format!(
+                "✅{} の参加メッセージを元に戻しました",
+                vc_channel_id.mention()
+            )
+        } else {
+            format!(
+                "❌{} には参加メッセージが設定されていません",
+                vc_channel_id.mention()
+            )
+        };
+        respond_ephemeral(ctx, interaction, content).await
+    }
+
+    /// 参加メッセージを設定するVCを取得する (`channel` の指定がなければ、実行したVCまたはそのスレッドのVC)
+    /// `channel` にこのBotが管理していないチャンネルが指定された場合はNoneを返す
+    async fn greeting_target_vc(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<Option<ChannelId>> {
+        if let Some(channel_id) = get_channel_option(interaction, "channel") {
+            let channel = get_channel(ctx, channel_id)
+                .await
+                .context("指定されたチャンネルの取得に失敗")?
+                .guild();
+            return Ok(channel
+                .filter(|channel| self.is_custom_vc(channel))
+                .map(|channel| channel.id));
+        }
+        Ok(match self.session_for_vc(&interaction.channel_id).await {
+            Some(session) => Some(session.vc_channel_id),
+            None => self
+                .session_for_thread(&interaction.channel_id)
+                .await
+                .map(|session| session.vc_channel_id),
+        })
+    }
+
+    /// VCのスレッドをロックする
+    async fn vclock_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // VCチャンネルを取得
+        let vc_channel = match self.get_vc(ctx, &interaction.channel_id).await {
+            Ok(vc_channel) => vc_channel,
+            Err(BotError::SessionNotFound) => {
+                return respond_ephemeral(ctx, interaction, "❌VCのスレッド内で実行してください")
+                    .await
+            }
+            Err(why) => return Err(why.into()),
+        };
+
+        // VCの権限をチェック
+        match vc_channel.permissions_for_user(&ctx, interaction.user.id) {
+            Ok(vc_permission) if vc_permission.manage_channels() => {}
+            _ => {
+                return respond_ephemeral(
+                    ctx,
+                    interaction,
+                    "❌VCのオーナーのみがスレッドをロックできます",
+                )
+                .await
+            }
+        };
+
+        // スレッドをロック
+        interaction
+            .channel_id
+            .edit_thread(ctx, |t| {
+                t.locked(true);
+                t
+            })
+            .await
+            .context("スレッドのロックに失敗")?;
+
+        // 返答
+        interaction
+            .create_interaction_response(&ctx, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|d| {
+                        d.content(format!(
+                            "🔒{} がスレッドをロックしました",
+                            interaction.user.mention()
+                        ));
+                        d.allowed_mentions(|m| m.empty_users());
+                        d
+                    });
+                r
+            })
+            .await
+            .context("結果の応答に失敗")?;
+
+        Ok(())
+    }
+
+    /// VCにいる全員を別のVCに移動する
+    async fn moveto_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // VCチャンネルを取得
+        let vc_channel = match self.get_vc(ctx, &interaction.channel_id).await {
+            Ok(vc_channel) => vc_channel,
+            Err(BotError::SessionNotFound) => {
+                return respond_ephemeral(ctx, interaction, "❌VCのスレッド内で実行してください")
+                    .await
+            }
+            Err(why) => return Err(why.into()),
+        };
+
+        // VCの権限をチェック
+        match vc_channel.permissions_for_user(&ctx, interaction.user.id) {
+            Ok(vc_permission) if vc_permission.manage_channels() => {}
+            _ => {
+                return respond_ephemeral(
+                    ctx,
+                    interaction,
+                    "❌VCのオーナーのみがメンバーを移動できます",
+                )
+                .await
+            }
+        };
+
+        // 移動先のVCを取得 (Botが管理しているVCのみ)
+        let target_vc = match get_channel_option(interaction, "target_vc") {
+            Some(target_vc_id) => get_channel(ctx, target_vc_id)
+                .await
+                .context("移動先のチャンネルの取得に失敗")?
+                .guild(),
+            None => None,
+        };
+        let target_vc = match target_vc {
+            Some(target_vc) if target_vc.id != vc_channel.id && self.is_custom_vc(&target_vc) => {
+                target_vc
+            }
+            _ => {
+                return respond_ephemeral(
+                    ctx,
+                    interaction,
+                    "❌移動先にはこのBotが管理している別のVCを指定してください",
+                )
+                .await
+            }
+        };
+
+        // 人数が多いと移動に時間がかかるので、先に応答しておく
+        interaction
+            .create_interaction_response(&ctx, |r| {
+                r.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+            })
+            .await
+            .context("コマンドの応答に失敗")?;
+
+        // VCにいるメンバーを移動する (移動に失敗したメンバーがいても、他のメンバーの移動は続ける)
+        // 応答済みなので、失敗した場合は応答を書き換えて知らせる
+        let members = match self.vc_members(ctx, &vc_channel.id).await {
+            Ok(members) => members,
+            Err(why) => {
+                interaction
+                    .edit_original_interaction_response(&ctx, |r| {
+                        r.content("❌VCのメンバーの取得に失敗したため、移動できませんでした");
+                        r
+                    })
+                    .await
+                    .context("エラー内容の応答に失敗")?;
+                return Err(why);
+            }
+        };
+        let mut moved = 0;
+        for member in &members {
+            match member.move_to_voice_channel(ctx, target_vc.id).await {
+                Ok(_) => moved += 1,
+                Err(why) => {
+                    error!("メンバー({})の移動に失敗: {:?}", member.user.id, why);
+                }
+            }
+        }
+
+        // 移動先のスレッドでも知らせる (まだスレッドがなければ、参加時に作成されるスレッドで分かる)
+        if let Some(target_session) = self.session_for_vc(&target_vc.id).await {
+            match target_session
+                .thread_channel_id
+                .send_message(ctx, |m| {
+                    m.content(format!(
+                        "🚚{} から{}人が移動してきました",
+                        vc_channel.id.mention(),
+                        moved
+                    ))
+                })
+                .await
+            {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("移動先のスレッドへの通知に失敗: {:?}", why);
+                }
+            }
+        }
+
+        // 返答
+        let content = if moved == members.len() {
+            format!(
+                "🚚{} がVCの{}人を {} に移動しました",
+                interaction.user.mention(),
+                moved,
+                target_vc.id.mention()
+            )
+        } else {
+            format!(
+                "🚚{} がVCの{}人を {} に移動しました ({}人は移動できませんでした)",
+                interaction.user.mention(),
+                moved,
+                target_vc.id.mention(),
+                members.len() - moved
+            )
+        };
+        interaction
+            .edit_original_interaction_response(&ctx, |r| {
+                r.content(content);
+                r.allowed_mentions(|m| m.empty_users());
+                r
+            })
+            .await
+            .context("結果の応答に失敗")?;
+
+        Ok(())
+    }
+
+    /// VCのスレッドのメッセージをピン留めする/ピン留めを外す
+    /// スレッドの参加者はメッセージの管理権限を持たないため、VCのオーナーがBotを通してピン留めできるようにする
+    async fn pinmessage_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        pin: bool,
+    ) -> Result<()> {
+        // VCチャンネルを取得
+        let vc_channel = match self.get_vc(ctx, &interaction.channel_id).await {
+            Ok(vc_channel) => vc_channel,
+            Err(BotError::SessionNotFound) => {
+                return respond_ephemeral(ctx, interaction, "❌VCのスレッド内で実行してください")
+                    .await
+            }
+            Err(why) => return Err(why.into()),
+        };
+
+        // VCの権限をチェック
+        match vc_channel.permissions_for_user(&ctx, interaction.user.id) {
+            Ok(vc_permission) if vc_permission.manage_channels() => {}
+            _ => {
+                return respond_ephemeral(
+                    ctx,
+                    interaction,
+                    "❌VCのオーナーのみがメッセージをピン留めできます",
+                )
+                .await
+            }
+        };
+
+        // メッセージIDを取得
+        let message_id = match get_string_option(interaction, "message_id")
+            .and_then(|id| id.trim().parse::<u64>().ok())
+        {
+            Some(id) => MessageId(id),
+            None => return respond_ephemeral(ctx, interaction, "❌メッセージIDが不正です").await,
+        };
+
+        // ピン留めする/ピン留めを外す (スレッドにないメッセージの場合は見つからない)
+        let thread_channel_id = interaction.channel_id;
+        let result = if pin {
+            thread_channel_id.pin(ctx, message_id).await
+        } else {
+            thread_channel_id.unpin(ctx, message_id).await
+        };
+        match result {
+            Ok(_) => {}
+            Err(SerenityError::Http(why)) if is_not_found(&why) => {
+                return respond_ephemeral(
+                    ctx,
+                    interaction,
+                    "❌このスレッドにそのメッセージは見つかりません",
+                )
+                .await
+            }
+            Err(why) => return Err(why).context("メッセージのピン留めの変更に失敗"),
+        }
+
+        // セッションの終了時にピン留めを外せるよう記録する
+        {
+            let mut pinned_messages = self.vc_pinned_messages.lock().await;
+            let pinned = pinned_messages.entry(vc_channel.id).or_default();
+            pinned.retain(|id| *id != message_id);
+            if pin {
+                pinned.push(message_id);
+            }
+        }
+
+        let content = if pin {
+            "📌メッセージをピン留めしました"
+        } else {
+            "メッセージのピン留めを外しました"
+        };
+        respond_ephemeral(ctx, interaction, content).await
+    }
+
+    /// VCのセッションの情報を表示する
+    async fn session_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // スレッドに対応するセッションを取得
+        let session = match self.session_for_thread(&interaction.channel_id).await {
+            Some(session) => session,
+            None => {
+                return respond_ephemeral(ctx, interaction, "❌VCのスレッド内で実行してください")
+                    .await
+            }
+        };
+
+        // VCの名前を取得
+        let vc_name = session
+            .vc_channel_id
+            .name(&ctx)
+            .await
+            .unwrap_or("不明なVC".to_string());
+        // 開始時刻と通話時間を計算
+        let elapsed = session.start_time.elapsed();
+        let started_at = Utc::now() - chrono::Duration::from_std(elapsed)?;
+        // 参加者リストを作成
+        let members = self
+            .vc_to_members
+            .lock()
+            .await
+            .get(&session.vc_channel_id)
+            .map(|members| {
+                members
+                    .iter()
+                    .map(|m| m.mention().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .filter(|members| !members.is_empty())
+            .unwrap_or("なし".to_string());
+        // 名前変更の履歴を作成
+        let rename_history = self
+            .rename_history_text(&session.vc_channel_id, &vc_name)
+            .await;
+
+        interaction
+            .create_interaction_response(&ctx, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|d| {
+                        d.embed(|e| {
+                            e.title(&vc_name);
+                            e.field("VC", session.vc_channel_id.mention(), true);
+                            e.field(
+                                "開始時刻",
+                                format!("<t:{}:f>", started_at.timestamp()),
+                                true,
+                            );
+                            e.field("通話時間", elapsed.hhmmss(), true);
+                            e.field("参加者", members, false);
+                            e.field("名前変更の履歴", rename_history, false);
+                            e
+                        });
+                        d.ephemeral(true);
+                        d
+                    });
+                r
+            })
+            .await
+            .context("コマンドの応答に失敗")?;
+
+        Ok(())
+    }
+
+    /// VCの名前変更の履歴を「変更前 → 変更後 - 変更したユーザー (時刻)」の形式で1行ずつ並べる
+    async fn rename_history_text(&self, vc_channel_id: &ChannelId, current_name: &str) -> String {
+        let rename_history = self.vc_rename_history.lock().await;
+        let history = match rename_history.get(vc_channel_id) {
+            Some(history) if !history.is_empty() => history,
+            _ => return "なし".to_string(),
+        };
+        // 変更後の名前は次の履歴の変更前の名前 (最後は今の名前)
+        let new_names = history
+            .iter()
+            .skip(1)
+            .map(|(name, _, _)| name.as_str())
+            .chain([current_name]);
+        history
+            .iter()
+            .zip(new_names)
+            .map(|((old_name, user_id, renamed_at), new_name)| {
+                format!(
+                    "`{}` → `{}` - {} (<t:{}:R>)",
+                    old_name,
+                    new_name,
+                    user_id.mention(),
+                    renamed_at.timestamp()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// VCの情報をスレッドに投稿する
+    async fn threadinfo_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // スレッドに対応するセッションを取得
+        let session = match self.session_for_thread(&interaction.channel_id).await {
+            Some(session) => session,
+            None => {
+                return respond_ephemeral(ctx, interaction, "❌VCのスレッド内で実行してください")
+                    .await
+            }
+        };
+
+        // VCの名前を取得
+        let vc_name = session
+            .vc_channel_id
+            .name(&ctx)
+            .await
+            .unwrap_or("不明なVC".to_string());
+        // 開始時刻を計算
+        let started_at = Utc::now() - chrono::Duration::from_std(session.start_time.elapsed())?;
+        // 現在の参加人数を取得
+        let member_count = self.vc_member_count(ctx, &session.vc_channel_id).await?;
+        // VCを作成したユーザーを取得
+        let creator = self
+            .vc_session_creator
+            .lock()
+            .await
+            .get(&session.vc_channel_id)
+            .map(|user_id| user_id.mention().to_string())
+            .unwrap_or("不明".to_string());
+        // スレッドでの人間の発言数を数える (最近100件まで)
+        let message_count = session
+            .thread_channel_id
+            .messages(&ctx, |f| {
+                f.limit(100);
+                f
+            })
+            .await
+            .context("メッセージ取得に失敗")?
+            .iter()
+            .filter(|m| !m.author.bot)
+            .count();
+        // 名前変更の履歴を作成
+        let rename_history = self
+            .rename_history_text(&session.vc_channel_id, &vc_name)
+            .await;
+
+        interaction
+            .create_interaction_response(&ctx, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|d| {
+                        d.embed(|e| {
+                            e.title(&vc_name);
+                            e.field("VC", session.vc_channel_id.mention(), true);
+                            e.field(
+                                "開始時刻",
+                                format!("<t:{}:f>", started_at.timestamp()),
+                                true,
+                            );
+                            e.field("参加人数", format!("{}人", member_count), true);
+                            e.field("作成者", creator, true);
+                            e.field("メッセージ数", format!("{}件", message_count), true);
+                            e.field("名前変更の履歴", rename_history, false);
+                            e
+                        });
+                        d.allowed_mentions(|m| m.empty_users());
+                        d
+                    });
+                r
+            })
+            .await
+            .context("コマンドの応答に失敗")?;
+
+        Ok(())
+    }
+
+    /// VCのセッションをすぐに終了する
+    async fn forceend_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // VCチャンネルを取得
+        let vc_channel = match self.get_vc(ctx, &interaction.channel_id).await {
+            Ok(vc_channel) => vc_channel,
+            Err(BotError::SessionNotFound) => {
+                return respond_ephemeral(ctx, interaction, "❌VCのスレッド内で実行してください")
+                    .await
+            }
+            Err(why) => return Err(why.into()),
+        };
+
+        // VCの権限をチェック
+        match vc_channel.permissions_for_user(&ctx, interaction.user.id) {
+            Ok(vc_permission) if vc_permission.manage_channels() => {}
+            _ => {
+                return respond_ephemeral(
+                    ctx,
+                    interaction,
+                    "❌VCのオーナーのみがセッションを終了できます",
+                )
+                .await
+            }
+        };
+
+        // 既に終了している場合は何もしない
+        if self.session_for_vc(&vc_channel.id).await.is_none() {
+            return respond_ephemeral(ctx, interaction, "❌そのVCは既に終了しています").await;
+        }
+
+        // 後始末に時間がかかっても応答が間に合うよう、先に返答する
+        // (応答に失敗してもセッションは終了させる)
+        let response = interaction
+            .create_interaction_response(&ctx, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|d| {
+                        d.content(format!(
+                            "✅{} がVCのセッションを終了しました",
+                            interaction.user.mention()
+                        ));
+                        d.allowed_mentions(|m| m.empty_users());
+                        d
+                    });
+                r
+            })
+            .await
+            .context("結果の応答に失敗");
+
+        // セッションを終了し、スレッドを削除/アーカイブする
+        self.end_session(ctx, &vc_channel.id, SessionEndReason::ForceEnded)
+            .await;
+        response?;
+
+        Ok(())
+    }
+
+    /// VCの名前を変更する
+    async fn renamevc_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // 新しい名前を取得
+        let name = match get_string_option(interaction, "name") {
+            Some(name) => name,
+            None => return respond_ephemeral(ctx, interaction, "❌名前を指定してください").await,
+        };
+
+        // VCの名前を変更
+        let result = self
+            .change_vc_name(
+                ctx,
+                &interaction.channel_id,
+                interaction.user.id,
+                interaction.member.as_ref(),
+                name,
+            )
+            .await?;
+
+        // 返答
+        let (content, ephemeral) = match result {
+            RenameResult::Renamed => (
+                format!("✅{} が名前を変更しました", interaction.user.mention()),
+                self.app_config.discord.ephemeral_success,
+            ),
+            RenameResult::Rejected(reason) => (reason, self.app_config.discord.ephemeral_errors),
+        };
+        interaction
+            .create_interaction_response(&ctx, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|d| {
+                        d.content(content);
+                        d.allowed_mentions(|m| m.empty_users());
+                        d.ephemeral(ephemeral);
+                        d
+                    });
+                r
+            })
+            .await
+            .context("結果の応答に失敗")?;
+
+        Ok(())
+    }
+
+    /// /renamevc の名前の入力候補を返す
+    /// 今のVCの名前、それに絵文字を付けたもの、最近の名前変更の履歴を候補にする
+    async fn renamevc_autocomplete(
+        &self,
+        ctx: &Context,
+        interaction: &AutocompleteInteraction,
+    ) -> Result<()> {
+        // スレッドに対応するVCを取得 (VCのスレッド以外では候補を出さない)
+        let vc_channel_id = match self.session_for_thread(&interaction.channel_id).await {
+            Some(session) => session.vc_channel_id,
+            None => return Ok(()),
+        };
+
+        // 候補を作成
+        let mut suggestions = Vec::new();
+        if let Some(vc_name) = vc_channel_id.name(&ctx).await {
+            suggestions.push(vc_name.clone());
+            for suffix in AUTOCOMPLETE_SUFFIXES {
+                suggestions.push(format!("{}{}", vc_name, suffix));
+            }
+        }
+        if let Some(history) = self.vc_rename_history.lock().await.get(&vc_channel_id) {
+            for (name, _, _) in history.iter().rev().take(2) {
+                suggestions.push(name.clone());
+            }
+        }
+        let mut seen = HashSet::new();
+        suggestions.retain(|name| seen.insert(name.clone()));
+        suggestions.truncate(MAX_AUTOCOMPLETE_SUGGESTIONS);
+
+        // 候補を返す
+        interaction
+            .create_autocomplete_response(&ctx.http, |r| {
+                for name in suggestions {
+                    r.add_string_choice(&name, &name);
+                }
+                r
+            })
+            .await
+            .context("入力候補の応答に失敗")?;
+
+        Ok(())
+    }
+
+    /// Botの使い方を表示する
+    async fn help_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        interaction
+            .create_interaction_response(&ctx, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|d| {
+                        d.add_embed(Self::help_embed());
+                        d.ephemeral(true);
+                        d
+                    });
+                r
+            })
+            .await
+            .context("コマンドの応答に失敗")?;
+
+        Ok(())
+    }
+
+    /// Botの使い方の説明
+    /// 機能を追加した際はここも更新する
+    pub(super) fn help_embed() -> CreateEmbed {
+        let mut embed = CreateEmbed::default();
+        embed.title("VCスレッドBotの使い方");
+        embed.field(
+            "スレッドの作成",
+            "VCに参加すると、自動的にVCと同じ名前の聞き専スレッドが作成されます",
+            false,
+        );
+        embed.field(
+            "チャンネル名の変更",
+            "スレッド内の「📝チャンネル名を変える」ボタンを押すと、VCの名前を変えられます (VCのオーナーのみ)",
+            false,
+        );
+        embed.field(
+            "VCの終了",
+            "VCが削除されると、会話があったスレッドは通話時間や参加者を記録してアーカイブされ、会話がなかったスレッドは削除されます",
+            false,
+        );
+        embed.field(
+            "コマンド",
+            [
+                "`/help` この説明を表示します",
+                "`/session` VCの通話時間や参加者、名前変更の履歴を表示します",
+                "`/renamevc` VCの名前を変更します (VCのオーナーのみ)",
+                "`/vclock` VCのスレッドをロックします (VCのオーナーのみ)",
+                "`/moveto` VCにいる全員を別のVCに移動します (VCのオーナーのみ)",
+                "`/pinmessage` `/unpinmessage` VCのスレッドのメッセージをピン留めする/外します (VCのオーナーのみ)",
+                "`/threadinfo` VCの情報をスレッドに投稿します",
+                "`/forceend` VCのセッションをすぐに終了します (VCのオーナーのみ)",
+                "`/unlink` VCとスレッドの紐づけを解除します (サーバー管理者のみ)",
+                "`/recreatethread` 削除されてしまったVCのスレッドを作り直します (サーバー管理者のみ)",
+                "`/cleanup` 既に存在しないVCやスレッドの記録を探します (サーバー管理者のみ)",
+                "`/setvcgreeting` VCのスレッドを作成したときの参加メッセージを設定します (チャンネル管理者のみ)",
+                "`/clearvcgreeting` VCの参加メッセージを元に戻します (チャンネル管理者のみ)",
+                "`/botconfig get|set|threadchannel` 起動中に変更できる設定を表示/変更します (サーバー管理者のみ)",
+                "`/stats` Botの統計を表示します (サーバー管理者のみ)",
+            ]
+            .join("\n"),
+            false,
+        );
+        embed
+    }
+
+    /// 起動中に変更できる設定を表示/変更する
+    async fn botconfig_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // サーバー管理権限をチェック
+        if !has_permission(interaction, Permissions::MANAGE_GUILD) {
+            return respond_ephemeral(ctx, interaction, "❌サーバー管理権限が必要です").await;
+        }
+
+        // サブコマンドとそのオプションを取得
+        let subcommand = match interaction.data.options.first() {
+            Some(subcommand) => subcommand,
+            None => return Ok(()),
+        };
+        if subcommand.name == "threadchannel" {
+            return self
+                .botconfig_threadchannel_command(ctx, interaction, subcommand)
+                .await;
+        }
+        let get_option = |name: &str| {
+            subcommand
+                .options
+                .iter()
+                .find(|o| o.name == name)
+                .and_then(|o| match &o.resolved {
+                    Some(CommandDataOptionValue::String(value)) => Some(value.clone()),
+                    _ => None,
+                })
+        };
+        let key = match get_option("key")
+            .as_deref()
+            .and_then(BotConfigKey::from_name)
+        {
+            Some(key) => key,
+            None => return respond_ephemeral(ctx, interaction, "❌設定名が不正です").await,
+        };
+
+        let content = match subcommand.name.as_str() {
+            "get" => {
+                let value = self.bot_config.read().await.get(key);
+                format!("`{}` = `{}`", key.name(), value)
+            }
+            "set" => {
+                let value = get_option("value").unwrap_or_default();
+                let mut bot_config = self.bot_config.write().await;
+                match bot_config.set(key, &value) {
+                    Ok(_) => format!(
+                        "✅`{}` を `{}` に変更しました (次のセッションから反映されます)",
+                        key.name(),
+                        bot_config.get(key)
+                    ),
+                    Err(reason) => format!("❌{}", reason),
+                }
+            }
+            _ => return Ok(()),
+        };
+        respond_ephemeral(ctx, interaction, content).await
+    }
+
+    /// VCのスレッドを作成するチャンネルを変更する (チャンネルを省略した場合は `thread_channel` に戻す)
+    async fn botconfig_threadchannel_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        subcommand: &CommandDataOption,
+    ) -> Result<()> {
+        let get_option = |name: &str| {
+            subcommand
+                .options
+                .iter()
+                .find(|o| o.name == name)
+                .and_then(|o| match &o.resolved {
+                    Some(CommandDataOptionValue::Channel(channel)) => Some(channel.id),
+                    _ => None,
+                })
+        };
+        let vc_channel_id = match get_option("vc") {
+            Some(vc_channel_id) => vc_channel_id,
+            None => return respond_ephemeral(ctx, interaction, "❌VCを指定してください").await,
+        };
+
+        let mut bot_config = self.bot_config.write().await;
+        let content = match get_option("channel") {
+            Some(thread_channel_id) => {
+                bot_config
+                    .vc_thread_channel_overrides
+                    .insert(vc_channel_id, thread_channel_id);
+                format!(
+                    "✅{} のスレッドを {} に作成するよう変更しました (次のセッションから反映されます)",
+                    vc_channel_id.mention(),
+                    thread_channel_id.mention()
+                )
+            }
+            None => {
+                bot_config
+                    .vc_thread_channel_overrides
+                    .remove(&vc_channel_id);
+                format!(
+                    "✅{} のスレッドを {} に作成するよう戻しました (次のセッションから反映されます)",
+                    vc_channel_id.mention(),
+                    interaction
+                        .guild_id
+                        .map_or(self.app_config.discord.thread_channel, |guild_id| {
+                            self.app_config.discord.thread_channel_for(guild_id)
+                        })
+                        .mention()
+                )
+            }
+        };
+        drop(bot_config);
+        respond_ephemeral(ctx, interaction, content).await
+    }
+
+    /// 起動してからの統計を表示する
+    async fn stats_command(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+    ) -> Result<()> {
+        // サーバー管理権限をチェック
+        if !has_permission(interaction, Permissions::MANAGE_GUILD) {
+            return respond_ephemeral(ctx, interaction, "❌サーバー管理権限が必要です").await;
+        }
+
+        // 進行中のVCの数を取得
+        let session_count = self.session_count().await;
+
+        interaction
+            .create_interaction_response(&ctx, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|d| {
+                        d.embed(|e| {
+                            e.title("Botの統計");
+                            e.field("稼働時間", self.start_time.elapsed().hhmmss(), false);
+                            e.field(
+                                "進行中のVC",
+                                format!(
+                                    "{} / {}",
+                                    session_count, self.app_config.discord.max_concurrent_sessions
+                                ),
+                                false,
+                            );
+                            e.field(
+                                "作成したスレッド",
+                                self.threads_created.load(Ordering::Relaxed),
+                                true,
+                            );
+                            e.field(
+                                "名前の変更",
+                                self.renames_performed.load(Ordering::Relaxed),
+                                true,
+                            );
+                            e.field(
+                                "会話があったVC",
+                                self.sessions_ended_with_messages.load(Ordering::Relaxed),
+                                true,
+                            );
+                            e.field(
+                                "会話がなかったVC",
+                                self.sessions_ended_without_messages.load(Ordering::Relaxed),
+                                true,
+                            );
+                            e.field(
+                                "後始末したスレッド",
+                                self.orphaned_threads_cleaned.load(Ordering::Relaxed),
+                                true,
+                            );
+                            e
+                        });
+                        d.ephemeral(true);
+                        d
+                    });
+                r
+            })
+            .await
+            .context("コマンドの応答に失敗")?;
+
+        Ok(())
+    }
+}
+
+/// スラッシュコマンドを作成する
+fn build_commands(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+    commands.create_application_command(|c| {
+        c.name("unlink");
+        c.description("VCとスレッドの紐づけを解除します");
+        c.default_member_permissions(Permissions::MANAGE_GUILD);
+        c.dm_permission(false);
+        c.create_option(|o| {
+            o.name("thread");
+            o.description("紐づけを解除するスレッド");
+            o.kind(CommandOptionType::Channel);
+            o.channel_types(&[ChannelType::PublicThread]);
+            o.required(true);
+            o
+        });
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("renamevc");
+        c.description("VCの名前を変更します");
+        c.dm_permission(false);
+        c.create_option(|o| {
+            o.name("name");
+            o.description("新しいVCの名前");
+            o.kind(CommandOptionType::String);
+            o.set_autocomplete(true);
+            o.required(true);
+            o
+        });
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("vclock");
+        c.description("VCのスレッドをロックします");
+        c.dm_permission(false);
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("moveto");
+        c.description("VCにいる全員を別のVCに移動します");
+        c.dm_permission(false);
+        c.create_option(|o| {
+            o.name("target_vc");
+            o.description("移動先のVC");
+            o.kind(CommandOptionType::Channel);
+            o.channel_types(&[ChannelType::Voice, ChannelType::Stage]);
+            o.required(true);
+            o
+        });
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("pinmessage");
+        c.description("VCのスレッドのメッセージをピン留めします");
+        c.dm_permission(false);
+        c.create_option(|o| {
+            o.name("message_id");
+            o.description("ピン留めするメッセージのID");
+            o.kind(CommandOptionType::String);
+            o.required(true);
+            o
+        });
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("unpinmessage");
+        c.description("VCのスレッドのメッセージのピン留めを外します");
+        c.dm_permission(false);
+        c.create_option(|o| {
+            o.name("message_id");
+            o.description("ピン留めを外すメッセージのID");
+            o.kind(CommandOptionType::String);
+            o.required(true);
+            o
+        });
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("session");
+        c.description("VCの通話時間や参加者、名前変更の履歴を表示します");
+        c.dm_permission(false);
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("threadinfo");
+        c.description("VCの情報をスレッドの全員に見えるように投稿します");
+        c.dm_permission(false);
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("forceend");
+        c.description("VCのセッションをすぐに終了し、スレッドを後始末します");
+        c.dm_permission(false);
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("recreatethread");
+        c.description("削除されてしまったVCのスレッドを作り直します");
+        c.default_member_permissions(Permissions::MANAGE_GUILD);
+        c.dm_permission(false);
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("cleanup");
+        c.description("既に存在しないVCやスレッドの記録を探します");
+        c.default_member_permissions(Permissions::MANAGE_GUILD);
+        c.dm_permission(false);
+        c.create_option(|o| {
+            o.name("fix");
+            o.description("見つかった記録を削除するか");
+            o.kind(CommandOptionType::Boolean);
+            o.required(false);
+            o
+        });
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("setvcgreeting");
+        c.description("VCのスレッドを作成したときの参加メッセージを設定します");
+        c.default_member_permissions(Permissions::MANAGE_CHANNELS);
+        c.dm_permission(false);
+        c.create_option(|o| {
+            o.name("message");
+            o.description(
+                "参加メッセージ ({user} は参加者、{channel_name} はVCの名前に置き換えられます)",
+            );
+            o.kind(CommandOptionType::String);
+            o.required(true);
+            o
+        });
+        c.create_option(|o| {
+            o.name("channel");
+            o.description("対象のVC (省略した場合は実行したVC)");
+            o.kind(CommandOptionType::Channel);
+            o.channel_types(&[ChannelType::Voice, ChannelType::Stage]);
+            o.required(false);
+            o
+        });
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("clearvcgreeting");
+        c.description("VCの参加メッセージを元に戻します");
+        c.default_member_permissions(Permissions::MANAGE_CHANNELS);
+        c.dm_permission(false);
+        c.create_option(|o| {
+            o.name("channel");
+            o.description("対象のVC (省略した場合は実行したVC)");
+            o.kind(CommandOptionType::Channel);
+            o.channel_types(&[ChannelType::Voice, ChannelType::Stage]);
+            o.required(false);
+            o
+        });
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("botconfig");
+        c.description("起動中に変更できる設定を表示/変更します");
+        c.default_member_permissions(Permissions::MANAGE_GUILD);
+        c.dm_permission(false);
+        c.create_option(|o| {
+            o.name("get");
+            o.description("設定の値を表示します");
+            o.kind(CommandOptionType::SubCommand);
+            o.create_sub_option(|o| {
+                o.name("key");
+                o.description("設定名");
+                o.kind(CommandOptionType::String);
+                o.required(true);
+                for key in BotConfigKey::ALL {
+                    o.add_string_choice(key.name(), key.name());
+                }
+                o
+            });
+            o
+        });
+        c.create_option(|o| {
+            o.name("set");
+            o.description("設定の値を変更します");
+            o.kind(CommandOptionType::SubCommand);
+            o.create_sub_option(|o| {
+                o.name("key");
+                o.description("設定名");
+                o.kind(CommandOptionType::String);
+                o.required(true);
+                for key in BotConfigKey::ALL {
+                    o.add_string_choice(key.name(), key.name());
+                }
+                o
+            });
+            o.create_sub_option(|o| {
+                o.name("value");
+                o.description("新しい値 (none を指定すると未設定に戻します)");
+                o.kind(CommandOptionType::String);
+                o.required(true);
+                o
+            });
+            o
+        });
+        c.create_option(|o| {
+            o.name("threadchannel");
+            o.description("VCのスレッドを作成するチャンネルを変更します");
+            o.kind(CommandOptionType::SubCommand);
+            o.create_sub_option(|o| {
+                o.name("vc");
+                o.description("対象のVC");
+                o.kind(CommandOptionType::Channel);
+                o.channel_types(&[ChannelType::Voice, ChannelType::Stage]);
+                o.required(true);
+                o
+            });
+            o.create_sub_option(|o| {
+                o.name("channel");
+                o.description(
+                    "スレッドを作成するチャンネル (省略した場合は既定のチャンネルに戻します)",
+                );
+                o.kind(CommandOptionType::Channel);
+                o.channel_types(&[ChannelType::Text, ChannelType::Forum]);
+                o.required(false);
+                o
+            });
+            o
+        });
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("help");
+        c.description("Botの使い方を表示します");
+        c
+    });
+    commands.create_application_command(|c| {
+        c.name("stats");
+        c.description("起動してからのBotの統計を表示します");
+        c.default_member_permissions(Permissions::MANAGE_GUILD);
+        c.dm_permission(false);
+        c
+    });
+    commands
+}
+
+/// Discord APIのエラーが、対象が存在しないこと(404)によるものか判定する
+fn is_not_found(why: &HttpError) -> bool {
+    why.status_code().map(|s| s.as_u16()) == Some(404)
+}
+
+/// チャンネルが存在するか判定する (存在しない場合以外のエラーはそのまま返す)
+async fn channel_exists(ctx: &Context, channel_id: ChannelId) -> Result<bool> {
+    if ctx.cache.channel(channel_id).is_some() {
+        return Ok(true);
+    }
+    match channel_id.to_channel(ctx).await {
+        Ok(_) => Ok(true),
+        Err(SerenityError::Http(why)) if is_not_found(&why) => Ok(false),
+        Err(why) => Err(why).context("チャンネルの取得に失敗"),
+    }
+}
+
+/// 実行したユーザーが権限を持っているか判定する
+fn has_permission(interaction: &ApplicationCommandInteraction, permission: Permissions) -> bool {
+    interaction
+        .member
+        .as_ref()
+        .and_then(|m| m.permissions)
+        .map(|p| p.contains(permission))
+        .unwrap_or(false)
+}
+
+/// チャンネルのオプションを取得する
+fn get_channel_option(
+    interaction: &ApplicationCommandInteraction,
+    name: &str,
+) -> Option<ChannelId> {
+    interaction
+        .data
+        .options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| match &o.resolved {
+            Some(CommandDataOptionValue::Channel(channel)) => Some(channel.id),
+            _ => None,
+        })
+}
+
+/// 真偽値のオプションを取得する
+fn get_bool_option(interaction: &ApplicationCommandInteraction, name: &str) -> Option<bool> {
+    interaction
+        .data
+        .options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| match &o.resolved {
+            Some(CommandDataOptionValue::Boolean(value)) => Some(*value),
+            _ => None,
+        })
+}
+
+/// 文字列のオプションを取得する
+fn get_string_option(interaction: &ApplicationCommandInteraction, name: &str) -> Option<String> {
+    interaction
+        .data
+        .options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| match &o.resolved {
+            Some(CommandDataOptionValue::String(value)) => Some(value.clone()),
+            _ => None,
+        })
+}
+
+/// 本人にのみ見えるメッセージで応答する
+async fn respond_ephemeral(
+    ctx: &Context,
+    interaction: &ApplicationCommandInteraction,
+    content: impl ToString,
+) -> Result<()> {
+    interaction
+        .create_interaction_response(&ctx, |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| {
+                    d.content(content);
+                    d.ephemeral(true);
+                    d
+                });
+            r
+        })
+        .await
+        .context("コマンドの応答に失敗")?;
+
+    Ok(())
+}
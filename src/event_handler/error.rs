@@ -0,0 +1,44 @@
+use std::fmt;
+
+/// イベント処理中のエラー
+#[derive(Debug)]
+pub enum BotError {
+    /// VCのセッションが見つからない (管理対象外のVC、または既に終了したVC)
+    SessionNotFound,
+    /// Discord APIの呼び出しに失敗
+    DiscordApi(serenity::Error),
+    /// その他のエラー
+    Internal(anyhow::Error),
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BotError::SessionNotFound => write!(f, "VCのセッションが見つかりません"),
+            BotError::DiscordApi(why) => write!(f, "Discord APIの呼び出しに失敗: {}", why),
+            BotError::Internal(why) => write!(f, "{:#}", why),
+        }
+    }
+}
+
+impl std::error::Error for BotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BotError::SessionNotFound => None,
+            BotError::DiscordApi(why) => Some(why),
+            BotError::Internal(why) => Some(why.as_ref()),
+        }
+    }
+}
+
+impl From<serenity::Error> for BotError {
+    fn from(why: serenity::Error) -> Self {
+        BotError::DiscordApi(why)
+    }
+}
+
+impl From<anyhow::Error> for BotError {
+    fn from(why: anyhow::Error) -> Self {
+        BotError::Internal(why)
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+
+use anyhow::{Context as _, Result};
+use log::{error, info};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use serenity::model::id::ChannelId;
+
+use super::Handler;
+use crate::app_config::{read_channel_id_list, DiscordConfig};
+
+/// 無視するチャンネルを、設定ファイルに直接書かれたものと `vc_ignored_channels_file` に書かれたものを合わせて作る
+pub(super) fn load_ignored_channels(discord: &DiscordConfig) -> Result<HashSet<ChannelId>> {
+    let mut channels = discord
+        .vc_ignored_channels
+        .iter()
+        .copied()
+        .collect::<HashSet<_>>();
+    if let Some(path) = &discord.vc_ignored_channels_file {
+        channels.extend(read_channel_id_list(path)?);
+    }
+    Ok(channels)
+}
+
+impl Handler {
+    /// `vc_ignored_channels_file` を読み込み直す
+    fn reload_ignored_channels(&self) -> Result<()> {
+        let channels = load_ignored_channels(&self.app_config.discord)?;
+        let count = channels.len();
+        *self
+            .vc_ignored_channels
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = channels;
+        info!("無視するチャンネルを読み込み直しました ({}件)", count);
+        Ok(())
+    }
+
+    /// `vc_ignored_channels_file` の変更を監視し、変更されたら再起動せずに読み込み直す
+    /// 監視は返り値のウォッチャーを破棄すると止まるので、起動している間は保持しておく
+    pub fn watch_ignored_channels_file(&self) -> Result<Option<RecommendedWatcher>> {
+        let path = match &self.app_config.discord.vc_ignored_channels_file {
+            Some(path) => path.clone(),
+            None => return Ok(None),
+        };
+
+        // エディタによっては保存時にファイルを置き換えるため、ファイルのあるディレクトリを監視する
+        let file_name = path.file_name().map(|name| name.to_owned());
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => ".".into(),
+        };
+        let handler = self.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let event = match event {
+                Ok(event) => event,
+                Err(why) => {
+                    error!("無視するチャンネルのファイルの監視に失敗: {:?}", why);
+                    return;
+                }
+            };
+            if event.kind.is_access()
+                || !event
+                    .paths
+                    .iter()
+                    .any(|p| p.file_name() == file_name.as_deref())
+            {
+                return;
+            }
+            // 書き込み途中などで読み込めない場合は、前の内容のままにする
+            match handler.reload_ignored_channels() {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("無視するチャンネルの読み込み直しに失敗: {:?}", why);
+                }
+            }
+        })
+        .context("ファイルの監視の開始に失敗")?;
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("ディレクトリの監視に失敗: {}", dir.display()))?;
+        Ok(Some(watcher))
+    }
+}
@@ -0,0 +1,432 @@
+//! DiscordのHTTP APIをモックサーバーに置き換えて、イベントの処理を通しで確認するテスト
+//! `cargo test --features integration-tests` で実行する
+
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+use serenity::cache::Cache;
+use serenity::client::bridge::gateway::ShardMessenger;
+use serenity::http::HttpBuilder;
+use serenity::model::channel::GuildChannel;
+use serenity::model::event::ChannelCreateEvent;
+use serenity::model::voice::VoiceState;
+use serenity::prelude::TypeMap;
+use wiremock::matchers::{method, path_regex};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+use super::test_fixtures::message_json;
+use super::*;
+use crate::app_config::AppConfigBuilder;
+
+/// サーバー
+const GUILD_ID: u64 = 1;
+/// カスタムVCのカテゴリ
+const VC_CATEGORY: u64 = 2;
+/// カスタムVC
+const VC_ID: u64 = 10;
+/// スレッドを作成するチャンネル
+const THREAD_CHANNEL: u64 = 20;
+/// 議題メッセージ
+const AGENDA_MESSAGE: u64 = 30;
+/// 作成されるスレッド (議題メッセージから作成するので同じID)
+const THREAD_ID: u64 = AGENDA_MESSAGE;
+/// Bot自身
+const BOT_ID: u64 = 100;
+
+/// テスト用のハンドラーと、モックサーバーにつながるコンテキストを作る
+async fn setup() -> (MockServer, Handler, Context) {
+    let server = MockServer::start().await;
+
+    let app_config = AppConfigBuilder::new()
+        .with_vc_category(ChannelId(VC_CATEGORY))
+        .with_thread_channel(ChannelId(THREAD_CHANNEL))
+        .with_discord(|discord| discord.post_vc_grace_period_secs = 0)
+        .build()
+        .expect("設定の作成に失敗");
+    let handler = Handler::new(app_config).expect("ハンドラーの作成に失敗");
+    *handler.ready_complete.lock().await = true;
+    handler
+        .bot_user_id
+        .set(UserId(BOT_ID))
+        .expect("BotのユーザーIDの設定に失敗");
+
+    let http = HttpBuilder::new("test-token")
+        .proxy(server.uri().as_str())
+        .expect("モックサーバーのURLが不正です")
+        .build();
+    let (tx, _rx) = futures::channel::mpsc::unbounded();
+    let ctx = Context {
+        data: Arc::new(RwLock::new(TypeMap::new())),
+        shard: ShardMessenger::new(tx),
+        shard_id: 0,
+        http: Arc::new(http),
+        cache: Arc::new(Cache::new()),
+    };
+
+    (server, handler, ctx)
+}
+
+/// 指定したパスへのリクエストに、JSONを返すよう登録する
+async fn mock_json(server: &MockServer, http_method: &str, path: &str, body: Value) {
+    Mock::given(method(http_method))
+        .and(path_regex(format!("^/api/v[0-9]+{}$", path)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        .mount(server)
+        .await;
+}
+
+/// モックサーバーが受け取った、指定したパスへのリクエスト
+async fn requests_to(server: &MockServer, http_method: &str, path: &str) -> Vec<Request> {
+    let suffix = Regex::new(&format!("^/api/v[0-9]+{}$", path)).unwrap();
+    server
+        .received_requests()
+        .await
+        .expect("リクエストの記録が無効です")
+        .into_iter()
+        .filter(|r| r.method.to_string() == http_method && suffix.is_match(r.url.path()))
+        .collect()
+}
+
+/// リクエストの本文をJSONとして読む
+fn body_json(request: &Request) -> Value {
+    serde_json::from_slice(&request.body).expect("本文がJSONではありません")
+}
+
+/// カスタムVCのJSON
+fn vc_json(name: &str) -> Value {
+    json!({
+        "id": VC_ID.to_string(),
+        "type": 2,
+        "guild_id": GUILD_ID.to_string(),
+        "parent_id": VC_CATEGORY.to_string(),
+        "name": name,
+        "position": 0,
+        "permission_overwrites": [],
+        "bitrate": 64000,
+        "user_limit": 0,
+        "nsfw": false,
+    })
+}
+
+/// 作成されるスレッドのJSON
+fn thread_json(name: &str) -> Value {
+    json!({
+        "id": THREAD_ID.to_string(),
+        "type": 11,
+        "guild_id": GUILD_ID.to_string(),
+        "parent_id": THREAD_CHANNEL.to_string(),
+        "name": name,
+        "position": 0,
+        "permission_overwrites": [],
+        "nsfw": false,
+        "thread_metadata": {
+            "archived": false,
+            "auto_archive_duration": 1440,
+            "archive_timestamp": "2022-08-01T00:00:00.000000+00:00",
+            "locked": false,
+        },
+    })
+}
+
+/// ユーザーがカスタムVCに参加した時のボイス状態
+fn join_voice_state(user_id: u64) -> VoiceState {
+    test_fixtures::voice_state(Some(GUILD_ID), Some(VC_ID), user_id)
+}
+
+/// カスタムVCをキャッシュに登録する (VCの名前はキャッシュから取得するため)
+fn cache_vc(ctx: &Context, name: &str) {
+    let mut event: ChannelCreateEvent =
+        serde_json::from_value(vc_json(name)).expect("チャンネルの作成に失敗");
+    ctx.cache.update(&mut event);
+}
+
+/// スレッドの作成に必要なAPIを登録する
+async fn mock_thread_creation(server: &MockServer) {
+    mock_json(
+        server,
+        "GET",
+        &format!("/channels/{}", VC_ID),
+        vc_json("テストVC"),
+    )
+    .await;
+    mock_json(
+        server,
+        "POST",
+        &format!("/channels/{}/messages", THREAD_CHANNEL),
+        message_json(AGENDA_MESSAGE, THREAD_CHANNEL, BOT_ID, true),
+    )
+    .await;
+    mock_json(
+        server,
+        "POST",
+        &format!(
+            "/channels/{}/messages/{}/threads",
+            THREAD_CHANNEL, AGENDA_MESSAGE
+        ),
+        thread_json("テストVC"),
+    )
+    .await;
+    mock_json(
+        server,
+        "POST",
+        &format!("/channels/{}/messages", VC_ID),
+        message_json(40, VC_ID, BOT_ID, true),
+    )
+    .await;
+    mock_json(
+        server,
+        "POST",
+        &format!("/channels/{}/messages", THREAD_ID),
+        message_json(50, THREAD_ID, BOT_ID, true),
+    )
+    .await;
+}
+
+/// スレッドが作成済みのセッションを登録する
+async fn insert_session(handler: &Handler) {
+    handler.sessions.lock().await.insert(
+        ChannelId(VC_ID),
+        ThreadState::Active(VcSession {
+            guild_id: GuildId(GUILD_ID),
+            vc_channel_id: ChannelId(VC_ID),
+            thread_channel_id: ChannelId(THREAD_ID),
+            agenda_message: None,
+            start_time: Instant::now(),
+            participants: HashSet::from([UserId(1)]),
+            summary_message_id: None,
+            message_count: 0,
+        }),
+    );
+}
+
+/// VCが削除された時に必要なAPIを登録する (スレッドのメッセージは引数で指定する)
+async fn mock_session_end(server: &MockServer, thread_messages: Value) {
+    mock_json(
+        server,
+        "GET",
+        &format!("/channels/{}/messages", THREAD_ID),
+        thread_messages,
+    )
+    .await;
+    mock_json(
+        server,
+        "GET",
+        &format!("/channels/{}/thread-members", THREAD_ID),
+        json!([]),
+    )
+    .await;
+    mock_json(
+        server,
+        "GET",
+        &format!("/channels/{}", THREAD_ID),
+        thread_json("テストVC"),
+    )
+    .await;
+    mock_json(
+        server,
+        "POST",
+        &format!("/channels/{}/messages", THREAD_ID),
+        message_json(50, THREAD_ID, BOT_ID, true),
+    )
+    .await;
+    mock_json(
+        server,
+        "PATCH",
+        &format!("/channels/{}", THREAD_ID),
+        thread_json("テストVC"),
+    )
+    .await;
+    mock_json(
+        server,
+        "DELETE",
+        &format!("/channels/{}", THREAD_ID),
+        thread_json("テストVC"),
+    )
+    .await;
+}
+
+/// 削除されたカスタムVC
+fn deleted_vc() -> GuildChannel {
+    serde_json::from_value(vc_json("テストVC")).expect("チャンネルの作成に失敗")
+}
+
+#[tokio::test]
+async fn join_creates_thread_named_after_vc() {
+    let (server, handler, ctx) = setup().await;
+    cache_vc(&ctx, "テストVC");
+    mock_thread_creation(&server).await;
+
+    handler
+        .voice_state_update(ctx.clone(), None, join_voice_state(1))
+        .await;
+
+    let requests = requests_to(
+        &server,
+        "POST",
+        &format!(
+            "/channels/{}/messages/{}/threads",
+            THREAD_CHANNEL, AGENDA_MESSAGE
+        ),
+    )
+    .await;
+    assert_eq!(requests.len(), 1);
+    assert_eq!(body_json(&requests[0])["name"], "テストVC");
+    assert_eq!(handler.session_count().await, 1);
+    let session = handler.session_for_vc(&ChannelId(VC_ID)).await.unwrap();
+    assert_eq!(session.thread_channel_id, ChannelId(THREAD_ID));
+    assert_eq!(
+        handler.session_for_thread(&ChannelId(THREAD_ID)).await,
+        Some(session)
+    );
+}
+
+#[tokio::test]
+async fn second_join_does_not_create_another_thread() {
+    let (server, handler, ctx) = setup().await;
+    cache_vc(&ctx, "テストVC");
+    mock_thread_creation(&server).await;
+
+    handler
+        .voice_state_update(ctx.clone(), None, join_voice_state(1))
+        .await;
+    handler
+        .voice_state_update(ctx.clone(), None, join_voice_state(2))
+        .await;
+
+    let requests = requests_to(
+        &server,
+        "POST",
+        &format!(
+            "/channels/{}/messages/{}/threads",
+            THREAD_CHANNEL, AGENDA_MESSAGE
+        ),
+    )
+    .await;
+    assert_eq!(requests.len(), 1);
+    assert_eq!(handler.session_count().await, 1);
+    let session = handler.session_for_vc(&ChannelId(VC_ID)).await.unwrap();
+    assert_eq!(session.participants, HashSet::from([UserId(1), UserId(2)]));
+}
+
+#[tokio::test]
+async fn vc_rename_renames_thread() {
+    let (server, handler, ctx) = setup().await;
+    insert_session(&handler).await;
+    mock_json(
+        &server,
+        "GET",
+        &format!("/channels/{}", VC_ID),
+        vc_json("新しいVC"),
+    )
+    .await;
+    mock_json(
+        &server,
+        "PATCH",
+        &format!("/channels/{}", THREAD_ID),
+        thread_json("新しいVC"),
+    )
+    .await;
+
+    // イベントの処理より前に、キャッシュは変更後のチャンネルに更新されている
+    cache_vc(&ctx, "新しいVC");
+    let old = serde_json::from_value(vc_json("テストVC")).unwrap();
+    let new = serde_json::from_value(vc_json("新しいVC")).unwrap();
+    handler
+        .channel_update(ctx.clone(), Some(Channel::Guild(old)), Channel::Guild(new))
+        .await;
+    // 定期的に行うスレッドの更新を、待たずに実行する
+    handler.flush_thread_updates(&ctx).await;
+
+    let requests = requests_to(&server, "PATCH", &format!("/channels/{}", THREAD_ID)).await;
+    assert_eq!(requests.len(), 1);
+    assert_eq!(body_json(&requests[0])["name"], "新しいVC");
+}
+
+#[tokio::test]
+async fn vc_delete_archives_thread_with_messages() {
+    let (server, handler, ctx) = setup().await;
+    insert_session(&handler).await;
+    mock_session_end(
+        &server,
+        json!([
+            message_json(52, THREAD_ID, 1, false),
+            message_json(51, THREAD_ID, BOT_ID, true),
+            message_json(50, THREAD_ID, BOT_ID, true),
+        ]),
+    )
+    .await;
+
+    handler.channel_delete(ctx.clone(), &deleted_vc()).await;
+
+    let archived = requests_to(&server, "PATCH", &format!("/channels/{}", THREAD_ID)).await;
+    assert_eq!(archived.len(), 1);
+    assert_eq!(body_json(&archived[0])["archived"], true);
+    assert!(
+        requests_to(&server, "DELETE", &format!("/channels/{}", THREAD_ID))
+            .await
+            .is_empty()
+    );
+    assert_eq!(handler.session_count().await, 0);
+}
+
+#[tokio::test]
+async fn vc_delete_deletes_thread_without_messages() {
+    let (server, handler, ctx) = setup().await;
+    insert_session(&handler).await;
+    mock_session_end(&server, json!([])).await;
+
+    handler.channel_delete(ctx.clone(), &deleted_vc()).await;
+
+    assert_eq!(
+        requests_to(&server, "DELETE", &format!("/channels/{}", THREAD_ID))
+            .await
+            .len(),
+        1
+    );
+    assert!(
+        requests_to(&server, "PATCH", &format!("/channels/{}", THREAD_ID))
+            .await
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn session_is_finalized_only_once() {
+    let (_server, handler, _ctx) = setup().await;
+    insert_session(&handler).await;
+
+    assert!(handler.begin_finalizing(&ChannelId(VC_ID)).await.is_some());
+    // 後始末中のセッションは進行中として扱わず、二重に後始末しない
+    assert!(handler.session_for_vc(&ChannelId(VC_ID)).await.is_none());
+    assert!(handler.begin_finalizing(&ChannelId(VC_ID)).await.is_none());
+    assert_eq!(handler.session_count().await, 1);
+
+    handler.finish_finalizing(&ChannelId(VC_ID)).await;
+    assert_eq!(handler.session_count().await, 0);
+}
+
+/// サーバーから削除された時のイベントを作成する
+fn removed_guild(unavailable: bool) -> UnavailableGuild {
+    serde_json::from_value(json!({
+        "id": GUILD_ID.to_string(),
+        "unavailable": unavailable,
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn guild_removal_forgets_sessions() {
+    let (_server, handler, ctx) = setup().await;
+    insert_session(&handler).await;
+
+    // 一時的に利用できないだけの場合は記録を残す
+    handler
+        .guild_delete(ctx.clone(), removed_guild(true), None)
+        .await;
+    assert_eq!(handler.session_count().await, 1);
+
+    handler
+        .guild_delete(ctx.clone(), removed_guild(false), None)
+        .await;
+    assert_eq!(handler.session_count().await, 0);
+}
@@ -0,0 +1,91 @@
+use anyhow::{Context as _, Result};
+use log::warn;
+use serenity::model::guild::{ScheduledEvent, ScheduledEventStatus};
+use serenity::prelude::*;
+
+use super::{get_channel, Handler, SessionEndReason};
+
+impl Handler {
+    /// イベントの状態が変わった時、VCのイベントであればスレッドを作成/後始末する
+    pub(super) async fn scheduled_event_updated(
+        &self,
+        ctx: &Context,
+        event: &ScheduledEvent,
+    ) -> Result<()> {
+        match event.status {
+            ScheduledEventStatus::Active => self.start_event_session(ctx, event).await,
+            ScheduledEventStatus::Completed | ScheduledEventStatus::Canceled => {
+                self.finish_event_session(ctx, event).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// イベントが始まった時、イベント名のスレッドを先に作成しておく
+    async fn start_event_session(&self, ctx: &Context, event: &ScheduledEvent) -> Result<()> {
+        // VCに紐づかないイベントは無視
+        let vc_channel_id = match event.channel_id {
+            Some(channel_id) => channel_id,
+            None => return Ok(()),
+        };
+
+        // カスタムVCでない場合は無視
+        let vc_channel = get_channel(ctx, vc_channel_id)
+            .await
+            .context("チャンネル取得失敗")?
+            .guild()
+            .ok_or(anyhow::anyhow!("チャンネルが存在しません"))?;
+        if !self.is_custom_vc(&vc_channel) {
+            return Ok(());
+        }
+
+        // 既にスレッドがある場合は何もしない
+        if self.session_for_vc(&vc_channel_id).await.is_some() {
+            return Ok(());
+        }
+
+        // イベントの作成者をスレッドの作成者にする
+        let creator_id = match event.creator_id {
+            Some(creator_id) => creator_id,
+            None => {
+                warn!(
+                    "イベントの作成者が不明なため、スレッドを作成しません: {}",
+                    event.name
+                );
+                return Ok(());
+            }
+        };
+        let creator = event
+            .guild_id
+            .member(ctx, creator_id)
+            .await
+            .context("イベントの作成者の取得に失敗")?;
+
+        // イベント名のスレッドを作成し、作成できた場合のみイベントのスレッドとして記録する
+        self.create_or_mention_thread_named(ctx, &vc_channel_id, &creator, Some(&event.name))
+            .await?;
+        if self.session_for_vc(&vc_channel_id).await.is_some() {
+            self.event_sessions
+                .lock()
+                .await
+                .insert(event.id, vc_channel_id);
+        }
+
+        Ok(())
+    }
+
+    /// イベントが終わった時、イベントのために作成したスレッドを後始末する
+    async fn finish_event_session(&self, ctx: &Context, event: &ScheduledEvent) -> Result<()> {
+        // イベントのために作成したスレッドでなければ何もしない
+        let vc_channel_id = match self.event_sessions.lock().await.remove(&event.id) {
+            Some(vc_channel_id) => vc_channel_id,
+            None => return Ok(()),
+        };
+
+        // セッションを終了し、スレッドを削除/アーカイブする
+        self.end_session(ctx, &vc_channel_id, SessionEndReason::EventEnded)
+            .await;
+
+        Ok(())
+    }
+}
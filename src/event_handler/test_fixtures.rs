@@ -0,0 +1,74 @@
+//! 単体テストと統合テストで共通して使う、Discordのオブジェクトの作成処理
+
+use serde_json::{json, Value};
+use serenity::model::channel::Message;
+use serenity::model::voice::VoiceState;
+
+/// ユーザーのJSON
+pub(super) fn user_json(user_id: u64, bot: bool) -> Value {
+    json!({
+        "id": user_id.to_string(),
+        "username": format!("user{}", user_id),
+        "discriminator": "0000",
+        "avatar": null,
+        "bot": bot,
+    })
+}
+
+/// メッセージのJSON
+pub(super) fn message_json(message_id: u64, channel_id: u64, author_id: u64, bot: bool) -> Value {
+    json!({
+        "id": message_id.to_string(),
+        "channel_id": channel_id.to_string(),
+        "author": user_json(author_id, bot),
+        "content": "",
+        "timestamp": "2022-08-01T00:00:00.000000+00:00",
+        "edited_timestamp": null,
+        "tts": false,
+        "mention_everyone": false,
+        "mentions": [],
+        "mention_roles": [],
+        "attachments": [],
+        "embeds": [],
+        "pinned": false,
+        "type": 0,
+    })
+}
+
+/// 指定したユーザーが投稿したメッセージ (IDは問わない場合に使う)
+pub(super) fn message(author_id: u64, bot: bool) -> Message {
+    serde_json::from_value(message_json(1, 1, author_id, bot)).expect("メッセージの作成に失敗")
+}
+
+/// ボイス状態 (サーバーを指定した場合はメンバー情報も付ける)
+pub(super) fn voice_state(
+    guild_id: Option<u64>,
+    channel_id: Option<u64>,
+    user_id: u64,
+) -> VoiceState {
+    let mut state = json!({
+        "channel_id": channel_id.map(|id| id.to_string()),
+        "user_id": user_id.to_string(),
+        "session_id": "session",
+        "deaf": false,
+        "mute": false,
+        "self_deaf": false,
+        "self_mute": false,
+        "self_stream": false,
+        "self_video": false,
+        "suppress": false,
+        "request_to_speak_timestamp": null,
+    });
+    if let Some(guild_id) = guild_id {
+        state["guild_id"] = json!(guild_id.to_string());
+        state["member"] = json!({
+            "guild_id": guild_id.to_string(),
+            "user": user_json(user_id, false),
+            "roles": [],
+            "joined_at": "2022-08-01T00:00:00.000000+00:00",
+            "deaf": false,
+            "mute": false,
+        });
+    }
+    serde_json::from_value(state).expect("ボイス状態の作成に失敗")
+}
@@ -0,0 +1,21 @@
+use std::net::SocketAddr;
+
+use anyhow::{Context as _, Result};
+use axum::{routing::get, Json, Router};
+use serde_json::{json, Value};
+
+/// ヘルスチェック用のHTTPサーバーを起動する
+pub async fn serve(port: u16) -> Result<()> {
+    let app = Router::new().route("/health", get(health));
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await
+        .context("ヘルスチェックサーバーの実行に失敗")?;
+    Ok(())
+}
+
+/// GET /health
+async fn health() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
@@ -1,12 +1,20 @@
 mod app_config;
+mod channel_utils;
 mod event_handler;
+mod health;
+mod metrics;
+mod telemetry;
+mod webhook;
 
 use anyhow::{Context as _, Result};
 use app_config::AppConfig;
-use event_handler::Handler;
-use std::env;
+use event_handler::{GuildScope, Handler};
+use log::error;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use serenity::framework::standard::StandardFramework;
+use serenity::model::id::GuildId;
 use serenity::prelude::*;
 
 /// メイン処理
@@ -18,31 +26,107 @@ async fn main() -> Result<()> {
     log4rs::init_file(format!("{}/log4rs.yml", basedir), Default::default())
         .context("log4rsの設定ファイルの読み込みに失敗")?;
 
-    // フレームワークを初期化
-    let framework = StandardFramework::new().configure(|c| c.prefix("~"));
-
     // 設定ファイルを読み込む
     let app_config = AppConfig::load_config(&basedir).context("設定ファイルの読み込みに失敗")?;
 
+    // ヘルスチェック用のHTTPサーバーを起動
+    if let Some(port) = app_config.health_port {
+        tokio::spawn(async move {
+            match health::serve(port).await {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("ヘルスチェックサーバーが停止しました: {:?}", why);
+                }
+            }
+        });
+    }
+
+    // トークンを取得
+    let token = app_config.token().context("トークンの取得に失敗")?;
+
+    // 専用のトークンを使うサーバーを、トークンごとにまとめる
+    let mut token_guilds: HashMap<String, HashSet<GuildId>> = HashMap::new();
+    for (guild_id, guild_token) in &app_config.discord.guild_tokens {
+        // 既定のトークンと同じ場合は既定のクライアントで扱う
+        if *guild_token != token {
+            token_guilds
+                .entry(guild_token.clone())
+                .or_default()
+                .insert(*guild_id);
+        }
+    }
+
     // イベント受信リスナーを構築
+    // 全てのクライアントで状態を共有できるよう、複製してから担当するサーバーを割り当てる
+    let metrics_port = app_config.metrics_port;
     let handler = Handler::new(app_config).context("イベント受信リスナーの構築に失敗")?;
+    let excluded_guilds = token_guilds.values().flatten().copied().collect();
+    let default_handler = handler.with_guild_scope(GuildScope::AllExcept(excluded_guilds));
+
+    // 無視するチャンネルのファイルの変更を監視 (起動している間はウォッチャーを保持する)
+    let _ignored_channels_watcher = handler
+        .watch_ignored_channels_file()
+        .context("無視するチャンネルのファイルの監視に失敗")?;
+
+    // メトリクスのHTTPサーバーを起動 (統計は全てのクライアントで共有されている)
+    if let Some(port) = metrics_port {
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            match metrics::serve(port, handler).await {
+                Ok(_) => {}
+                Err(why) => {
+                    error!("メトリクスサーバーが停止しました: {:?}", why);
+                }
+            }
+        });
+    }
+
+    // いずれかのクライアントが停止したら(専用のトークンで接続できない場合を含む)、Bot全体を終了する
+    let (stopped_tx, mut stopped_rx) = tokio::sync::mpsc::unbounded_channel();
+
+    // 専用のトークンを使うクライアントを別タスクで起動
+    for (guild_token, guilds) in token_guilds {
+        let mut client = build_client(
+            guild_token,
+            handler.with_guild_scope(GuildScope::Only(guilds)),
+        )
+        .await?;
+        let stopped_tx = stopped_tx.clone();
+        tokio::spawn(async move {
+            let result = client
+                .start()
+                .await
+                .context("専用トークンのBot動作中にエラーが発生しました");
+            let _ = stopped_tx.send(result);
+        });
+    }
 
-    // 環境変数のトークンを使用してDiscord APIを初期化
-    let token = env::var("DISCORD_TOKEN").context("トークンが指定されていません")?;
+    // 既定のトークンのクライアントでイベント受信を開始
+    let mut client = build_client(token, default_handler).await?;
+    tokio::spawn(async move {
+        let result = client
+            .start()
+            .await
+            .context("Bot動作中にエラーが発生しました");
+        let _ = stopped_tx.send(result);
+    });
+
+    stopped_rx.recv().await.unwrap_or(Ok(()))
+}
+
+/// トークンを使用してDiscord APIのクライアントを構築する
+async fn build_client(token: String, handler: Handler) -> Result<Client> {
+    // フレームワークを初期化
+    let framework = StandardFramework::new().configure(|c| c.prefix("~"));
+
+    // イベント受信リスナーはシャード間で共有できるようArcで包む
     let intents = GatewayIntents::non_privileged()
         | GatewayIntents::MESSAGE_CONTENT
         | GatewayIntents::GUILD_MEMBERS;
-    let mut client = Client::builder(token, intents)
-        .event_handler(handler)
+    let client = Client::builder(token, intents)
+        .event_handler_arc(Arc::new(handler))
         .framework(framework)
         .await
         .context("Botの初期化に失敗")?;
-
-    // イベント受信を開始
-    client
-        .start()
-        .await
-        .context("Bot動作中にエラーが発生しました")?;
-
-    Ok(())
+    Ok(client)
 }
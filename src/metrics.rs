@@ -0,0 +1,96 @@
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+
+use anyhow::{Context as _, Result};
+use axum::{http::header, response::IntoResponse, routing::get, Extension, Router};
+
+use crate::event_handler::{Handler, Metrics};
+
+/// Prometheusのテキスト形式のContent-Type
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Prometheusから収集するためのメトリクスのHTTPサーバーを起動する
+pub async fn serve(port: u16, handler: Handler) -> Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .layer(Extension(handler));
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await
+        .context("メトリクスサーバーの実行に失敗")?;
+    Ok(())
+}
+
+/// GET /metrics
+async fn metrics(Extension(handler): Extension<Handler>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, CONTENT_TYPE)],
+        render(&handler.metrics().await),
+    )
+}
+
+/// メトリクスをPrometheusのテキスト形式にする
+fn render(metrics: &Metrics) -> String {
+    let entries = [
+        (
+            "discord_vc_sessions_created_total",
+            "counter",
+            "作成したセッション(スレッド)の数",
+            metrics.sessions_created,
+        ),
+        (
+            "discord_vc_sessions_ended_total",
+            "counter",
+            "終了したセッションの数",
+            metrics.sessions_ended,
+        ),
+        (
+            "discord_vc_renames_total",
+            "counter",
+            "VCの名前を変更した回数",
+            metrics.renames,
+        ),
+        (
+            "discord_vc_active_sessions",
+            "gauge",
+            "管理中のセッションの数",
+            metrics.active_sessions,
+        ),
+        (
+            "discord_vc_api_errors_total",
+            "counter",
+            "Discord APIの呼び出しに失敗した回数",
+            metrics.api_errors,
+        ),
+    ];
+
+    let mut text = String::new();
+    for (name, kind, help, value) in entries {
+        // Stringへの書き込みは失敗しない
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} {}", name, kind);
+        let _ = writeln!(text, "{} {}", name, value);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_prometheus_text_format() {
+        let text = render(&Metrics {
+            sessions_created: 3,
+            sessions_ended: 2,
+            renames: 5,
+            active_sessions: 1,
+            api_errors: 0,
+        });
+        assert!(text.contains("# TYPE discord_vc_sessions_created_total counter\ndiscord_vc_sessions_created_total 3\n"));
+        assert!(text
+            .contains("# TYPE discord_vc_active_sessions gauge\ndiscord_vc_active_sessions 1\n"));
+        assert!(text.contains("discord_vc_api_errors_total 0\n"));
+    }
+}
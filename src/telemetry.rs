@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, Utc};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+/// CSVに記録するセッションの情報
+#[derive(Debug, serde::Serialize)]
+pub struct SessionRecord {
+    /// セッションのID
+    pub session_id: String,
+    /// VCのチャンネルID
+    pub vc_channel_id: String,
+    /// スレッドのチャンネルID
+    pub thread_channel_id: String,
+    /// 開始時刻 (ISO8601)
+    pub start_time: String,
+    /// 終了時刻 (ISO8601)
+    pub end_time: String,
+    /// 通話時間(秒)
+    pub duration_seconds: i64,
+    /// 参加者の数
+    pub participant_count: usize,
+    /// スレッドでの人間の発言数
+    pub message_count: usize,
+    /// スレッドを削除したか
+    pub was_deleted: bool,
+}
+
+impl SessionRecord {
+    /// 開始/終了時刻から通話時間を計算して記録を作る
+    pub fn new(
+        vc_channel_id: impl ToString,
+        thread_channel_id: impl ToString,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        participant_count: usize,
+        message_count: usize,
+        was_deleted: bool,
+    ) -> Self {
+        Self {
+            session_id: uuid::Uuid::new_v4().to_string(),
+            vc_channel_id: vc_channel_id.to_string(),
+            thread_channel_id: thread_channel_id.to_string(),
+            start_time: start_time.to_rfc3339(),
+            end_time: end_time.to_rfc3339(),
+            duration_seconds: (end_time - start_time).num_seconds(),
+            participant_count,
+            message_count,
+            was_deleted,
+        }
+    }
+}
+
+/// セッションの記録をCSVファイルに1行追記する
+/// ファイルがまだない場合はヘッダーも書き込む
+pub async fn append_session(path: &Path, record: &SessionRecord) -> Result<()> {
+    let write_header = tokio::fs::metadata(path).await.is_err();
+
+    // CSVの行を作成
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(write_header)
+        .from_writer(vec![]);
+    writer
+        .serialize(record)
+        .context("セッションの記録の作成に失敗")?;
+    let data = writer
+        .into_inner()
+        .context("セッションの記録の作成に失敗")?;
+
+    // ファイルに追記
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .context("記録ファイルを開けませんでした")?;
+    file.write_all(&data)
+        .await
+        .context("記録ファイルへの書き込みに失敗")?;
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use anyhow::{Context as _, Result};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+/// Webhook送信用のHTTPクライアント
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+
+/// WebhookにJSONをPOSTする
+/// サーバーエラー(5xx)が返ってきた場合は1度だけ再試行する
+pub async fn fire_webhook(url: &str, payload: Value) -> Result<()> {
+    let mut response = post(url, &payload).await?;
+    if response.status().is_server_error() {
+        response = post(url, &payload).await?;
+    }
+    response
+        .error_for_status()
+        .context("Webhookがエラーを返しました")?;
+    Ok(())
+}
+
+/// WebhookにJSONを1度POSTする
+async fn post(url: &str, payload: &Value) -> Result<reqwest::Response> {
+    CLIENT
+        .post(url)
+        .json(payload)
+        .send()
+        .await
+        .context("Webhookの送信に失敗")
+}